@@ -0,0 +1,286 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, Result};
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::prelude::*;
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::config::AccountsConfig;
+
+// Accounts by name, each a JSON-encoded Account
+const ACCOUNTS: TableDefinition<&str, &[u8]> = TableDefinition::new("accounts");
+
+pub const MIN_PASSWORD: usize = 8;
+const MAX_NAME: usize = 20;
+
+// Wrong passwords from one address, or for one account, before sign ins from there or to it
+// are refused for a while without checking. It spans connections, unlike the handler's limit
+const MAX_FAILURES: usize = 10;
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+static ACCOUNTS_DB: OnceLock<(Database, bool)> = OnceLock::new();
+static FAILURES: LazyLock<Mutex<Failures>> = LazyLock::new(|| Mutex::new(Failures::default()));
+// Argon2 takes a good fraction of a second and 19 MiB per hash, so hashes run on the blocking
+// pool, a few at a time, rather than on the workers serving everyone else
+static HASHING: LazyLock<Semaphore> = LazyLock::new(|| Semaphore::new(std::thread::available_parallelism().map_or(2, |n| n.get())));
+// Checked in place of the password of a name nobody has, so that takes as long as a wrong password
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| hash("not anyone's password").unwrap_or_default());
+
+// Define the Account struct with what is stored for one reader
+#[derive(Deserialize, Serialize)]
+struct Account {
+    // Argon2id PHC string, salt and parameters included
+    password: String,
+    // RFC 3339, when the account was registered
+    created: String,
+    // What the account's bookmarks are kept under, random so no resume code or handle reaches
+    // them. 0 for accounts made before there were IDs, which get one when they next sign in
+    #[serde(default)]
+    id: u64,
+}
+
+/// A reader who got their name and password right
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SignedIn {
+    pub name: String,
+    pub id: u64,
+}
+
+/// Why a sign in didn't work
+#[derive(Debug, PartialEq)]
+pub(crate) enum LoginError {
+    Wrong,
+    // Too many wrong passwords lately from the reader's address or for the account
+    Throttled,
+}
+
+// Recent wrong passwords by address and by account name
+#[derive(Default)]
+struct Failures {
+    by_ip: HashMap<IpAddr, VecDeque<Instant>>,
+    by_name: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Failures {
+    // Forget failures older than the window, and the addresses and names left with none
+    fn expire(&mut self, now: Instant) {
+        let expire = |failures: &mut VecDeque<Instant>| {
+            while failures.front().is_some_and(|failed| now.duration_since(*failed) >= FAILURE_WINDOW) {
+                failures.pop_front();
+            }
+            !failures.is_empty()
+        };
+        self.by_ip.retain(|_, failures| expire(failures));
+        self.by_name.retain(|_, failures| expire(failures));
+    }
+
+    fn throttled(&mut self, ip: Option<IpAddr>, name: &str, now: Instant) -> bool {
+        self.expire(now);
+        let too_many = |failures: Option<&VecDeque<Instant>>| failures.is_some_and(|failures| failures.len() >= MAX_FAILURES);
+        ip.is_some_and(|ip| too_many(self.by_ip.get(&ip))) || too_many(self.by_name.get(name))
+    }
+
+    fn record(&mut self, ip: Option<IpAddr>, name: &str, now: Instant) {
+        if let Some(ip) = ip {
+            self.by_ip.entry(ip).or_default().push_back(now);
+        }
+        self.by_name.entry(name.to_string()).or_default().push_back(now);
+    }
+}
+
+fn failures() -> MutexGuard<'static, Failures> {
+    FAILURES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Opens (or creates) the accounts database when one is configured, accounts are off otherwise
+pub fn init(config: &AccountsConfig) -> Result<()> {
+    let Some(path) = &config.database else {
+        return Ok(());
+    };
+    let database = Database::create(path).map_err(|e| Error::other(format!("Failed to open accounts database {:?}: {}", path, e)))?;
+    // Create the table up front so reads never find it missing
+    let txn = database.begin_write().map_err(Error::other)?;
+    txn.open_table(ACCOUNTS).map_err(Error::other)?;
+    txn.commit().map_err(Error::other)?;
+    let _ = ACCOUNTS_DB.set((database, config.guests));
+    Ok(())
+}
+
+pub(crate) fn enabled() -> bool {
+    ACCOUNTS_DB.get().is_some()
+}
+
+/// Whether readers may skip signing in, always when accounts are off
+pub(crate) fn guests_allowed() -> bool {
+    ACCOUNTS_DB.get().map(|(_, guests)| *guests).unwrap_or(true)
+}
+
+/// Names are case-insensitive and kept short enough for the admin console and templates
+pub(crate) fn normalize_name(name: &str) -> std::result::Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.chars().count() > MAX_NAME || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("A name is 1 to {} letters, digits, - or _.", MAX_NAME));
+    }
+    Ok(name)
+}
+
+fn database() -> std::result::Result<&'static Database, String> {
+    ACCOUNTS_DB.get().map(|(database, _)| database).ok_or_else(|| "Accounts are off.".to_string())
+}
+
+pub(crate) fn exists(name: &str) -> bool {
+    find(name).is_some()
+}
+
+fn find(name: &str) -> Option<Account> {
+    let txn = database().ok()?.begin_read().ok()?;
+    let table = txn.open_table(ACCOUNTS).ok()?;
+    let account = table.get(name).ok()??;
+    serde_json::from_slice(account.value()).ok()
+}
+
+// Run password hashing, and the database work that goes with it, on the blocking pool
+async fn off_the_workers<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    let _permit = HASHING.acquire().await.unwrap_or_else(|e| panic!("The hashing semaphore closed: {}", e));
+    tokio::task::spawn_blocking(work).await.unwrap_or_else(|e| panic!("Password hashing failed: {}", e))
+}
+
+fn hash(password: &str) -> std::result::Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+// A random account ID, never 0
+fn new_id() -> u64 {
+    OsRng.next_u64().max(1)
+}
+
+/// Creates an account, returning who it was created for
+pub(crate) async fn register(name: &str, password: &str) -> std::result::Result<SignedIn, String> {
+    let name = normalize_name(name)?;
+    if password.chars().count() < MIN_PASSWORD {
+        return Err(format!("Passwords need at least {} characters.", MIN_PASSWORD));
+    }
+    let password = password.to_string();
+    off_the_workers(move || create(name, &password)).await
+}
+
+fn create(name: String, password: &str) -> std::result::Result<SignedIn, String> {
+    let id = new_id();
+    let account = serde_json::to_vec(&Account { password: hash(password)?, created: Utc::now().to_rfc3339(), id }).map_err(|e| e.to_string())?;
+
+    let failed = |e: redb::Error| {
+        tracing::warn!("Failed to save account {}: {}", name, e);
+        "The account could not be saved, try again later.".to_string()
+    };
+    let txn = database()?.begin_write().map_err(|e| failed(e.into()))?;
+    {
+        let mut table = txn.open_table(ACCOUNTS).map_err(|e| failed(e.into()))?;
+        // Checked inside the write transaction so two readers can't register the same name at once
+        if table.get(name.as_str()).map_err(|e| failed(e.into()))?.is_some() {
+            return Err(format!("The name {} is taken.", name));
+        }
+        table.insert(name.as_str(), account.as_slice()).map_err(|e| failed(e.into()))?;
+    }
+    txn.commit().map_err(|e| failed(e.into()))?;
+    tracing::info!(account = %name, "Registered account");
+    Ok(SignedIn { name, id })
+}
+
+/// The account when the password is right for it. Too many wrong passwords from `ip` or for the
+/// name lately and it isn't checked at all
+pub(crate) async fn verify(name: &str, password: &str, ip: Option<IpAddr>) -> std::result::Result<SignedIn, LoginError> {
+    // A name that can't exist is still checked against the dummy hash and counted
+    let name = normalize_name(name).unwrap_or_else(|_| name.trim().to_lowercase());
+    if failures().throttled(ip, &name, Instant::now()) {
+        return Err(LoginError::Throttled);
+    }
+    let password = password.to_string();
+    let checked = name.clone();
+    let signed_in = off_the_workers(move || check(&checked, &password)).await;
+    if signed_in.is_none() {
+        failures().record(ip, &name, Instant::now());
+    }
+    signed_in.ok_or(LoginError::Wrong)
+}
+
+// Check a password, hashing it either way so unknown names take as long as wrong passwords
+fn check(name: &str, password: &str) -> Option<SignedIn> {
+    let account = find(name);
+    let stored = account.as_ref().map_or(DUMMY_HASH.as_str(), |account| account.password.as_str());
+    let right = PasswordHash::new(stored).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok());
+    let mut account = account.filter(|_| right)?;
+    if account.id == 0 {
+        account.id = new_id();
+        if let Err(e) = save(name, &account) {
+            tracing::warn!("Failed to give account {} an ID: {}", name, e);
+            return None;
+        }
+    }
+    Some(SignedIn { name: name.to_string(), id: account.id })
+}
+
+fn save(name: &str, account: &Account) -> std::result::Result<(), String> {
+    let account = serde_json::to_vec(account).map_err(|e| e.to_string())?;
+    let txn = database()?.begin_write().map_err(|e| e.to_string())?;
+    txn.open_table(ACCOUNTS).map_err(|e| e.to_string())?.insert(name, account.as_slice()).map_err(|e| e.to_string())?;
+    txn.commit().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn register_and_verify() {
+        let path = std::env::temp_dir().join(format!("telzine-accounts-{}.redb", std::process::id()));
+        init(&AccountsConfig { database: Some(path.clone()), guests: true }).unwrap();
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+
+        let alice = register("Alice", "correct horse").await.unwrap();
+        assert_eq!(alice.name, "alice");
+        assert_ne!(alice.id, 0);
+        assert!(register("alice", "another password").await.unwrap_err().contains("taken"));
+        assert!(register("bob", "short").await.is_err());
+
+        assert_eq!(verify(" ALICE ", "correct horse", ip).await, Ok(alice));
+        assert_eq!(verify("alice", "wrong horse", ip).await, Err(LoginError::Wrong));
+        assert_eq!(verify("nobody", "correct horse", ip).await, Err(LoginError::Wrong));
+        assert_eq!(verify("no one!", "correct horse", ip).await, Err(LoginError::Wrong));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn failures_throttle_by_address_and_name() {
+        let mut failures = Failures::default();
+        let now = Instant::now();
+        let ip = Some(IpAddr::from([198, 51, 100, 7]));
+        let other_ip = Some(IpAddr::from([198, 51, 100, 8]));
+        for i in 0..MAX_FAILURES {
+            assert!(!failures.throttled(ip, "carol", now));
+            failures.record(ip, &format!("name{}", i), now);
+        }
+        // Too many from one address, whatever name it tries
+        assert!(failures.throttled(ip, "carol", now));
+        assert!(!failures.throttled(other_ip, "carol", now));
+        for _ in 0..MAX_FAILURES {
+            failures.record(None, "carol", now);
+        }
+        // Too many for one name, wherever they come from
+        assert!(failures.throttled(other_ip, "carol", now));
+        assert!(!failures.throttled(other_ip, "dave", now));
+        // And it wears off
+        let later = now + FAILURE_WINDOW;
+        assert!(!failures.throttled(ip, "carol", later));
+        assert!(failures.by_ip.is_empty() && failures.by_name.is_empty());
+    }
+}
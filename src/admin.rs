@@ -0,0 +1,265 @@
+use std::io::Result;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+use crate::chat;
+use crate::comments;
+use crate::listener;
+use crate::sessions::{self, Control, SessionInfo};
+use crate::zine::Magazine;
+use crate::zine_handler::Shelf;
+
+const PROMPT: &str = "admin> ";
+
+const HELP: &str = "\
+list                     Connected readers
+kick <id> [message]      Disconnect a reader, showing them the message
+ban <ip|id>              Refuse an address (or a reader's address) until restart, kicking its readers
+unban <ip>               Lift a ban
+bans                     Banned addresses
+broadcast <message>      Show every reader a message
+reload                   Reload the magazine, readers get it when they next see the cover
+queue                    Comments waiting for approval
+approve <id>             Show a comment to readers
+reject <id>              Delete a comment, waiting or approved
+help                     This list
+quit                     Leave the console
+";
+
+// Define the AdminServer struct that serves the operator console on a Unix socket only its owner can use
+pub(crate) struct AdminServer {
+    listener: UnixListener,
+    path: PathBuf,
+    // The user running telzine, the only one let in
+    owner: u32,
+    shelf: Shelf,
+    magazine_path: PathBuf,
+}
+
+impl AdminServer {
+    // Bind the console socket, replacing a stale one, and restrict it to the user running telzine.
+    // The socket is made in a directory only we can enter and moved into place once it's 0600,
+    // so there is never a moment other users could connect to it
+    pub fn bind(path: &Path, shelf: Shelf, magazine_path: PathBuf) -> Result<Self> {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        listener::remove_stale_socket(path)?;
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let private = parent.join(format!(".telzine-admin-{}", std::process::id()));
+        std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+        let staged = private.join("admin.sock");
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+        let _ = std::fs::remove_file(&staged);
+        std::fs::remove_dir(&private)?;
+        let listener = bound?;
+        let owner = std::fs::symlink_metadata(path)?.uid();
+        Ok(AdminServer { listener, path: path.to_path_buf(), owner, shelf, magazine_path })
+    }
+
+    pub async fn run(self) -> Result<()> {
+        info!("Admin console on: unix:{}", self.path.display());
+        let console = Arc::new((self.shelf, self.magazine_path));
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            // The file mode should already keep everyone else out, this makes sure of it
+            match stream.peer_cred() {
+                Ok(cred) if cred.uid() == self.owner => {}
+                Ok(cred) => {
+                    warn!(uid = cred.uid(), "Refused admin console connection from another user");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Refused admin console connection with unknown credentials: {}", e);
+                    continue;
+                }
+            }
+            let console = console.clone();
+            tokio::spawn(async move {
+                let (shelf, magazine_path) = &*console;
+                if let Err(e) = serve(stream, shelf, magazine_path).await {
+                    warn!("Error handling admin connection: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve(stream: UnixStream, shelf: &Shelf, magazine_path: &Path) -> Result<()> {
+    debug!("Admin console opened");
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(format!("telzine admin console, type help for commands\n{}", PROMPT).as_bytes()).await?;
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').map(|(c, a)| (c, a.trim())).unwrap_or((line, ""));
+        let reply = match command {
+            "" => String::new(),
+            "list" | "ls" => list(),
+            "kick" => kick(args),
+            "ban" => ban(args),
+            "unban" => unban(args),
+            "bans" => sessions::bans().iter().map(|ip| format!("{}\n", ip)).collect(),
+            "broadcast" | "say" if !args.is_empty() => {
+                info!(message = args, "Admin broadcast");
+                format!("Sent to {} readers\n", sessions::broadcast(args))
+            }
+            "broadcast" | "say" => "Usage: broadcast <message>\n".to_string(),
+            "reload" => reload(shelf, magazine_path).await,
+            "queue" => queue(),
+            "approve" => approve(args),
+            "reject" => reject(args),
+            "help" | "?" => HELP.to_string(),
+            "quit" | "exit" => break,
+            _ => format!("Unknown command {:?}, type help for commands\n", line),
+        };
+        writer.write_all(format!("{}{}", reply, PROMPT).as_bytes()).await?;
+    }
+    debug!("Admin console closed");
+    Ok(())
+}
+
+fn list() -> String {
+    let sessions = sessions::list();
+    if sessions.is_empty() {
+        return "No readers connected\n".to_string();
+    }
+    let mut out = format!("{:<6} {:<8} {:<40} {:<20} {:<16} {:<9} {:<9} {:<8} {}\n", "ID", "FRONTEND", "ADDRESS", "USER", "TERMINAL", "SIZE", "PAGE", "IDLE", "CONNECTED");
+    for SessionInfo { id, frontend, peer, user, terminal, size, page, started, last_input, .. } in sessions {
+        let size = size.map(|(width, height)| format!("{}x{}", width, height)).unwrap_or_else(|| "-".to_string());
+        let page = match page {
+            Some((section, page)) => format!("{}/{}", section, page),
+            None if chat::is_member(id) => "chat".to_string(),
+            None => "cover".to_string(),
+        };
+        out.push_str(&format!(
+            "{:<6} {:<8} {:<40} {:<20} {:<16} {:<9} {:<9} {:<8} {}\n",
+            id,
+            frontend,
+            peer.to_string(),
+            user.as_deref().unwrap_or("-"),
+            terminal.as_deref().unwrap_or("-"),
+            size,
+            page,
+            format_duration(last_input.elapsed()),
+            format_duration(started.elapsed()),
+        ));
+    }
+    out
+}
+
+fn kick(args: &str) -> String {
+    let (id, message) = args.split_once(' ').unwrap_or((args, ""));
+    let Ok(id) = id.parse::<u64>() else {
+        return "Usage: kick <id> [message]\n".to_string();
+    };
+    let message = if message.trim().is_empty() { "You have been disconnected." } else { message.trim() };
+    if sessions::send(id, Control::Kick(message.to_string())) {
+        info!(session = id, "Admin kicked session");
+        format!("Kicked session {}\n", id)
+    } else {
+        format!("No session {}\n", id)
+    }
+}
+
+fn ban(args: &str) -> String {
+    let ip = match (args.parse::<IpAddr>(), args.parse::<u64>()) {
+        (Ok(ip), _) => ip,
+        (_, Ok(id)) => match sessions::list().into_iter().find(|info| info.id == id) {
+            Some(info) => match info.peer.ip() {
+                Some(ip) => ip,
+                None => return format!("Session {} came in over a Unix socket and has no address to ban\n", id),
+            },
+            None => return format!("No session {}\n", id),
+        },
+        _ => return "Usage: ban <ip|id>\n".to_string(),
+    };
+    let kicked = sessions::ban(ip);
+    info!(%ip, "Admin banned address");
+    format!("Banned {}, kicked {} readers\n", ip, kicked)
+}
+
+fn unban(args: &str) -> String {
+    match args.parse::<IpAddr>() {
+        Ok(ip) if sessions::unban(ip) => {
+            info!(%ip, "Admin lifted ban");
+            format!("Unbanned {}\n", ip)
+        }
+        Ok(ip) => format!("{} is not banned\n", ip),
+        Err(_) => "Usage: unban <ip>\n".to_string(),
+    }
+}
+
+fn queue() -> String {
+    let pending = comments::pending();
+    if pending.is_empty() {
+        return "No comments waiting\n".to_string();
+    }
+    let mut out = String::new();
+    for comment in pending {
+        out.push_str(&format!("#{} by {} on {} at {}\n", comment.id, comment.author, comment.section, comment.posted));
+        for line in comment.text.lines() {
+            out.push_str(&format!("    {}\n", line));
+        }
+    }
+    out
+}
+
+fn approve(args: &str) -> String {
+    match args.trim_start_matches('#').parse::<u64>() {
+        Ok(id) if comments::approve(id) => {
+            info!(comment = id, "Admin approved comment");
+            format!("Approved comment {}\n", id)
+        }
+        Ok(id) => format!("No comment {} waiting\n", id),
+        Err(_) => "Usage: approve <id>\n".to_string(),
+    }
+}
+
+fn reject(args: &str) -> String {
+    match args.trim_start_matches('#').parse::<u64>() {
+        Ok(id) if comments::reject(id) => {
+            info!(comment = id, "Admin rejected comment");
+            format!("Deleted comment {}\n", id)
+        }
+        Ok(id) => format!("No comment {}\n", id),
+        Err(_) => "Usage: reject <id>\n".to_string(),
+    }
+}
+
+async fn reload(shelf: &Shelf, magazine_path: &Path) -> String {
+    let path = magazine_path.to_path_buf();
+    let loaded = tokio::task::spawn_blocking(move || Magazine::try_from_directory(&path))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    match loaded {
+        Ok(magazine) => {
+            let sections = magazine.sections.len();
+            *shelf.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(magazine);
+            info!(sections, "Admin reloaded magazine");
+            format!("Reloaded {:?} with {} sections\n", magazine_path, sections)
+        }
+        Err(reason) => {
+            warn!("Magazine reload failed: {}", reason);
+            format!("Reload failed, still serving the previous magazine: {}\n", reason)
+        }
+    }
+}
+
+// 42s, 3m05s or 1h02m
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
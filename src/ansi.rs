@@ -0,0 +1,160 @@
+use crossterm::style::Color;
+
+// Pages are rendered to ANSI escape sequences for terminals. Exports to other formats
+// read those sequences back into runs of text with the style they were drawn in.
+
+pub(crate) type Rgb = (u8, u8, u8);
+
+// Define the Style struct that tracks the SGR attributes in effect for a run of text
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Style {
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+    pub underline_color: Option<Rgb>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+/// Splits rendered text into runs of identically styled text, dropping every escape
+/// sequence that isn't a color or text attribute (cursor movement, screen clearing)
+pub(crate) fn segments(text: &str) -> Vec<(Style, String)> {
+    let mut segments: Vec<(Style, String)> = Vec::new();
+    let mut style = Style::default();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.peek() != Some(&'[') {
+                    chars.next();
+                    continue;
+                }
+                chars.next();
+                let mut params = String::new();
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        if c == 'm' {
+                            apply_sgr(&mut style, &params);
+                        }
+                        break;
+                    }
+                    params.push(c);
+                }
+            }
+            '\n' | '\t' => push(&mut segments, &style, c),
+            c if c.is_control() => {}
+            c => push(&mut segments, &style, c),
+        }
+    }
+    segments
+}
+
+/// The text a reader sees with every escape sequence removed
+pub(crate) fn strip(text: &str) -> String {
+    segments(text).into_iter().map(|(_, text)| text).collect()
+}
+
+/// A line a reader typed, made safe to show other readers: no escape sequences or other
+/// control characters, and no bidirectional overrides to disguise text
+pub(crate) fn printable(line: &str) -> String {
+    strip(line)
+        .chars()
+        .filter(|&c| !c.is_control() && !matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'))
+        .collect()
+}
+
+fn push(segments: &mut Vec<(Style, String)>, style: &Style, c: char) {
+    match segments.last_mut() {
+        Some((last, text)) if last == style => text.push(c),
+        _ => segments.push((style.clone(), c.to_string())),
+    }
+}
+
+fn apply_sgr(style: &mut Style, params: &str) {
+    let codes: Vec<u16> = params.split(';').map(|code| code.parse().unwrap_or(0)).collect();
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 => *style = Style::default(),
+            1 => style.bold = true,
+            3 => style.italic = true,
+            4 => style.underline = true,
+            22 => style.bold = false,
+            23 => style.italic = false,
+            24 => style.underline = false,
+            code @ 30..=37 => style.fg = Some(palette((code - 30) as u8)),
+            code @ 90..=97 => style.fg = Some(palette((code - 90 + 8) as u8)),
+            code @ 40..=47 => style.bg = Some(palette((code - 40) as u8)),
+            code @ 100..=107 => style.bg = Some(palette((code - 100 + 8) as u8)),
+            39 => style.fg = None,
+            49 => style.bg = None,
+            59 => style.underline_color = None,
+            target @ (38 | 48 | 58) => {
+                let (color, used) = extended_color(&codes[i + 1..]);
+                i += used;
+                match target {
+                    38 => style.fg = color,
+                    48 => style.bg = color,
+                    _ => style.underline_color = color,
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+// "5;n" picks from the 256 color palette, "2;r;g;b" is truecolor; returns the color and how many codes it took
+fn extended_color(codes: &[u16]) -> (Option<Rgb>, usize) {
+    match codes {
+        [5, index, ..] => (Some(palette(*index as u8)), 2),
+        [2, r, g, b, ..] => (Some((*r as u8, *g as u8, *b as u8)), 4),
+        _ => (None, codes.len()),
+    }
+}
+
+// The RGB value a terminal color is drawn in, None for the terminal's default
+pub(crate) fn color_rgb(color: Color) -> Option<Rgb> {
+    let index = match color {
+        Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15,
+        Color::AnsiValue(index) => index,
+        Color::Rgb { r, g, b } => return Some((r, g, b)),
+        Color::Reset => return None,
+    };
+    Some(palette(index))
+}
+
+/// The xterm 256 color palette: 16 system colors, a 6x6x6 cube and a gray ramp
+pub(crate) fn palette(index: u8) -> Rgb {
+    const SYSTEM: [Rgb; 16] = [
+        (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+        (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+    ];
+    match index {
+        0..=15 => SYSTEM[index as usize],
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+use crate::config::BookmarksConfig;
+use crate::zine::Magazine;
+
+// Resume codes leave out letters and digits that are easy to mix up when read off a screen
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+// About 50 bits. Codes handed out when they were 6 characters long still work
+const CODE_LENGTH: usize = 10;
+// Named bookmarks one reader can keep, so a single session can't grow the store without end
+pub const MAX_BOOKMARKS: usize = 50;
+const MAX_HANDLE: usize = 20;
+// How often changes are written out; the last few seconds of them are lost if the server is killed
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+const DAY: u64 = 24 * 60 * 60;
+// Codes and handles that match no one, allowed from one address within the window
+const MAX_FAILURES: usize = 10;
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+static STORE: LazyLock<Mutex<Store>> = LazyLock::new(|| Mutex::new(Store::default()));
+static FAILURES: LazyLock<Mutex<Failures>> = LazyLock::new(|| Mutex::new(Failures::default()));
+
+/// A page that stays put when the magazine is reloaded: the section's directory and the
+/// page's number rather than their positions, which change as pages are added
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Place {
+    pub section: String,
+    pub page: u32,
+}
+
+impl Place {
+    // The place of a 0-based section and page
+    pub fn of(magazine: &Magazine, section: usize, page: usize) -> Option<Self> {
+        let current = magazine.get_section(section)?;
+        Some(Place { section: current.directory.clone(), page: current.get_page(page)?.page_number })
+    }
+
+    // The 0-based section and page in this edition, None when it's gone
+    pub fn find(&self, magazine: &Magazine) -> Option<(usize, usize)> {
+        let section = magazine.sections.iter().position(|section| section.directory == self.section)?;
+        let page = magazine.sections[section].pages.iter().position(|page| page.page_number == self.page)?;
+        Some((section, page))
+    }
+}
+
+// Define the Reader struct with what the server remembers about one reader
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Reader {
+    // Where they were when they last turned a page
+    #[serde(default)]
+    pub last: Option<Place>,
+    #[serde(default)]
+    pub bookmarks: BTreeMap<String, Place>,
+    // When their code or handle was last used, in seconds since the epoch. Readers from before
+    // this was kept count as seen when the store is loaded
+    #[serde(default = "now")]
+    pub seen: u64,
+}
+
+/// Whose bookmarks they are: a guest's, by resume code, or a signed-in reader's, by their
+/// account's ID, which nothing typed at the resume command can reach
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Owner {
+    Code(String),
+    Account(u64),
+}
+
+/// How a returning reader was found. A code brings them back in full; a handle is easy to
+/// guess, so it only lets them look at the bookmarks behind it
+#[derive(Debug, PartialEq)]
+pub(crate) enum Resumed {
+    Code(String),
+    Handle(String),
+}
+
+/// Why a resume didn't work
+#[derive(Debug, PartialEq)]
+pub(crate) enum ResumeError {
+    Unknown,
+    // Too many codes and handles that matched no one lately from the reader's address
+    Throttled,
+}
+
+// Recent codes and handles that matched no one, by address. Readers without one, on a Unix
+// socket, share an allowance
+#[derive(Default)]
+struct Failures {
+    by_ip: HashMap<Option<IpAddr>, VecDeque<Instant>>,
+}
+
+impl Failures {
+    // Forget failures older than the window, and the addresses left with none
+    fn expire(&mut self, now: Instant) {
+        self.by_ip.retain(|_, failures| {
+            while failures.front().is_some_and(|failed| now.duration_since(*failed) >= FAILURE_WINDOW) {
+                failures.pop_front();
+            }
+            !failures.is_empty()
+        });
+    }
+
+    fn throttled(&mut self, ip: Option<IpAddr>, now: Instant) -> bool {
+        self.expire(now);
+        self.by_ip.get(&ip).is_some_and(|failures| failures.len() >= MAX_FAILURES)
+    }
+
+    fn record(&mut self, ip: Option<IpAddr>, now: Instant) {
+        self.by_ip.entry(ip).or_default().push_back(now);
+    }
+}
+
+fn failures() -> MutexGuard<'static, Failures> {
+    FAILURES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Guests by resume code, the handles they picked pointing at their codes, and signed-in readers by account ID
+#[derive(Default, Deserialize, Serialize)]
+struct Store {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    readers: BTreeMap<String, Reader>,
+    handles: BTreeMap<String, String>,
+    #[serde(default)]
+    accounts: BTreeMap<u64, Reader>,
+    #[serde(skip)]
+    keep: Duration,
+    // Changed since it was last written out
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Store {
+    fn reader(&mut self, owner: &Owner) -> &mut Reader {
+        match owner {
+            Owner::Code(code) => self.readers.entry(code.clone()).or_default(),
+            Owner::Account(id) => self.accounts.entry(*id).or_default(),
+        }
+    }
+
+    // Forget guests not seen within `keep` of `now`, and the handles pointing at them. Accounts
+    // are kept for as long as they can sign in
+    fn prune(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.keep.as_secs());
+        let before = self.readers.len();
+        self.readers.retain(|_, reader| reader.seen >= cutoff);
+        if self.readers.len() != before {
+            let readers = &self.readers;
+            self.handles.retain(|_, code| readers.contains_key(code));
+            self.dirty = true;
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
+
+fn store() -> MutexGuard<'static, Store> {
+    STORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Loads the bookmarks file when one is configured and starts saving changes to it in the
+/// background, they're kept in memory only otherwise
+pub fn init(config: &BookmarksConfig) -> Result<()> {
+    let keep = Duration::from_secs(config.keep_days * DAY);
+    let Some(path) = &config.store else {
+        store().keep = keep;
+        return Ok(());
+    };
+    let mut loaded = match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str::<Store>(&text)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse bookmarks file {:?}: {}", path, e)))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Store::default(),
+        Err(e) => return Err(Error::new(e.kind(), format!("Failed to read bookmarks file {:?}: {}", path, e))),
+    };
+    loaded.path = Some(path.clone());
+    loaded.keep = keep;
+    *store() = loaded;
+    tokio::spawn(save_periodically(path.clone()));
+    Ok(())
+}
+
+// Every few seconds, prune the store and write it out if anything changed. It's serialized
+// under the lock but written on the blocking pool, so page turns never wait for the disk
+async fn save_periodically(path: PathBuf) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let json = {
+            let mut store = store();
+            store.prune(now());
+            if !std::mem::take(&mut store.dirty) {
+                continue;
+            }
+            serde_json::to_vec(&*store)
+        };
+        let destination = path.clone();
+        let written = tokio::task::spawn_blocking(move || write(&destination, json.map_err(Error::other)?))
+            .await
+            .unwrap_or_else(|e| Err(Error::other(e)));
+        if let Err(e) = written {
+            tracing::warn!("Failed to save bookmarks to {:?}: {}", path, e);
+            store().dirty = true;
+        }
+    }
+}
+
+// Write the store out whole, through a temporary file so a crash never leaves half of it
+fn write(path: &Path, json: Vec<u8>) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, json)?;
+    fs::rename(&temporary, path)
+}
+
+/// A new reader with a resume code nobody has yet, for when they first want something remembered
+pub(crate) fn new_reader() -> String {
+    let mut store = store();
+    loop {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos()).unwrap_or_default();
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(seed);
+        let mut bits = hasher.finish();
+        let code: String = (0..CODE_LENGTH)
+            .map(|_| {
+                let c = CODE_ALPHABET[(bits % CODE_ALPHABET.len() as u64) as usize] as char;
+                bits /= CODE_ALPHABET.len() as u64;
+                c
+            })
+            .collect();
+        if !store.readers.contains_key(&code) {
+            store.readers.insert(code.clone(), Reader { seen: now(), ..Reader::default() });
+            store.dirty = true;
+            return code;
+        }
+    }
+}
+
+/// Who a code or handle a reader typed belongs to, if anyone. Addresses that keep missing are
+/// turned away for a while, so codes can't be found by trying them all
+pub(crate) fn lookup(code_or_handle: &str, ip: Option<IpAddr>) -> std::result::Result<Resumed, ResumeError> {
+    let attempted = Instant::now();
+    if failures().throttled(ip, attempted) {
+        return Err(ResumeError::Throttled);
+    }
+    let mut store = store();
+    let code = code_or_handle.trim().to_uppercase();
+    let resumed = if store.readers.contains_key(&code) {
+        Resumed::Code(code)
+    } else if let Some(code) = store.handles.get(&code_or_handle.trim().to_lowercase()) {
+        Resumed::Handle(code.clone())
+    } else {
+        failures().record(ip, attempted);
+        return Err(ResumeError::Unknown);
+    };
+    let (Resumed::Code(code) | Resumed::Handle(code)) = &resumed;
+    if let Some(reader) = store.readers.get_mut(code) {
+        reader.seen = now();
+        store.dirty = true;
+    }
+    Ok(resumed)
+}
+
+pub(crate) fn get(owner: &Owner) -> Option<Reader> {
+    let store = store();
+    match owner {
+        Owner::Code(code) => store.readers.get(code).cloned(),
+        Owner::Account(id) => store.accounts.get(id).cloned(),
+    }
+}
+
+/// Change what's remembered about a reader, adding them if they're new, to be saved shortly
+pub(crate) fn update(owner: &Owner, change: impl FnOnce(&mut Reader)) {
+    let mut store = store();
+    let reader = store.reader(owner);
+    change(reader);
+    reader.seen = now();
+    store.dirty = true;
+}
+
+/// Let a reader come back by a name of their choosing instead of their code
+pub(crate) fn claim(handle: &str, code: &str) -> std::result::Result<String, String> {
+    let handle = handle.trim().to_lowercase();
+    if handle.is_empty() || handle.chars().count() > MAX_HANDLE || !handle.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("A handle is 1 to {} letters, digits, - or _.", MAX_HANDLE));
+    }
+    let mut store = store();
+    // A handle spelling someone's code could never be resumed by
+    let taken = store.readers.contains_key(&handle.to_uppercase());
+    match store.handles.get(&handle) {
+        Some(owner) if owner == code => return Ok(handle),
+        Some(_) => return Err(format!("The handle {} is taken.", handle)),
+        None if taken => return Err(format!("The handle {} is taken.", handle)),
+        None => {}
+    }
+    // One handle per reader, picking a new one gives up the old
+    store.handles.retain(|_, owner| owner != code);
+    store.handles.insert(handle.clone(), code.to_string());
+    store.readers.entry(code.to_string()).or_default().seen = now();
+    store.dirty = true;
+    Ok(handle)
+}
+
+/// The handle a reader picked, if any
+pub(crate) fn handle(code: &str) -> Option<String> {
+    store().handles.iter().find(|(_, owner)| owner.as_str() == code).map(|(handle, _)| handle.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_by_code_and_handle() {
+        let ip = Some(IpAddr::from([198, 51, 100, 1]));
+        let code = new_reader();
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert_eq!(lookup(&code.to_lowercase(), ip), Ok(Resumed::Code(code.clone())));
+        assert_eq!(lookup(&format!(" {} ", code), ip), Ok(Resumed::Code(code.clone())));
+
+        let handle = format!("reader-{}", code.to_lowercase());
+        assert_eq!(claim(&handle.to_uppercase(), &code), Ok(handle.clone()));
+        assert_eq!(lookup(&handle, ip), Ok(Resumed::Handle(code.clone())));
+        assert_eq!(super::handle(&code), Some(handle.clone()));
+        // Someone else can't take it, or pick a name that reads as a code
+        let other = new_reader();
+        assert!(claim(&handle, &other).is_err());
+        assert!(claim(&code, &other).is_err());
+        assert!(claim("no spaces", &other).is_err());
+
+        assert_eq!(lookup("nobody-has-this", ip), Err(ResumeError::Unknown));
+        assert_eq!(lookup("", ip), Err(ResumeError::Unknown));
+    }
+
+    #[test]
+    fn guessing_codes_is_throttled_per_address() {
+        let ip = Some(IpAddr::from([198, 51, 100, 2]));
+        let other_ip = Some(IpAddr::from([198, 51, 100, 3]));
+        let code = new_reader();
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(lookup("NOT4CODE", ip), Err(ResumeError::Unknown));
+        }
+        // Even the right code waits out the window now, but other addresses don't
+        assert_eq!(lookup(&code, ip), Err(ResumeError::Throttled));
+        assert_eq!(lookup(&code, other_ip), Ok(Resumed::Code(code.clone())));
+
+        let mut failures = Failures::default();
+        let now = Instant::now();
+        failures.record(ip, now);
+        assert!(!failures.throttled(ip, now + FAILURE_WINDOW));
+        assert!(failures.by_ip.is_empty());
+    }
+
+    #[test]
+    fn accounts_are_out_of_resume_reach() {
+        let owner = Owner::Account(4_242_424_242);
+        update(&owner, |reader| reader.last = Some(Place { section: "example1".to_string(), page: 1 }));
+        assert!(get(&owner).and_then(|reader| reader.last).is_some());
+        assert_eq!(lookup("4242424242", Some(IpAddr::from([198, 51, 100, 4]))), Err(ResumeError::Unknown));
+        assert_eq!(get(&Owner::Code("4242424242".to_string())).map(|reader| reader.last), None);
+    }
+
+    #[test]
+    fn prune_forgets_old_readers_and_their_handles() {
+        let mut store = Store { keep: Duration::from_secs(10 * DAY), ..Store::default() };
+        let now = 100 * DAY;
+        store.readers.insert("OLD".to_string(), Reader { seen: now - 11 * DAY, ..Reader::default() });
+        store.readers.insert("NEW".to_string(), Reader { seen: now - 9 * DAY, ..Reader::default() });
+        store.handles.insert("old".to_string(), "OLD".to_string());
+        store.handles.insert("new".to_string(), "NEW".to_string());
+        store.accounts.insert(7, Reader { seen: 0, ..Reader::default() });
+        store.prune(now);
+        assert!(store.dirty);
+        assert_eq!(store.readers.keys().collect::<Vec<_>>(), ["NEW"]);
+        assert_eq!(store.handles.keys().collect::<Vec<_>>(), ["new"]);
+        assert!(store.accounts.contains_key(&7));
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use chrono::prelude::*;
+use crossterm::style::Stylize;
+
+use crate::ansi;
+use crate::config::ChatConfig;
+use crate::sessions::{self, Control};
+
+/// Longest message a reader can send, in characters
+pub const MAX_MESSAGE: usize = 300;
+// The window messages_per_minute is counted over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Undoes the chat's split screen without moving the cursor, for anything that takes a reader out of it
+pub const UNSPLIT: &str = "\x1b7\x1b[r\x1b8";
+
+static ROOM: LazyLock<Mutex<Room>> = LazyLock::new(|| Mutex::new(Room::default()));
+
+// Define the Member struct for one reader in the chat room
+struct Member {
+    nick: String,
+    // When their recent messages were sent, for rate limiting
+    sent: VecDeque<Instant>,
+}
+
+// Who is in the room by session ID, and the last things said there, already rendered
+#[derive(Default)]
+struct Room {
+    enabled: bool,
+    scrollback_size: usize,
+    messages_per_minute: usize,
+    members: BTreeMap<u64, Member>,
+    scrollback: VecDeque<String>,
+}
+
+fn room() -> MutexGuard<'static, Room> {
+    ROOM.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn init(config: &ChatConfig) {
+    let mut room = room();
+    room.enabled = config.enabled;
+    room.scrollback_size = config.scrollback;
+    room.messages_per_minute = config.messages_per_minute;
+}
+
+pub(crate) fn enabled() -> bool {
+    room().enabled
+}
+
+// Add a line to the scrollback and show it to everyone in the room but `except`. The room is
+// unlocked before the line goes out, as the admin console's broadcast locks the sessions first
+fn announce(mut room: MutexGuard<'static, Room>, line: String, except: Option<u64>) {
+    room.scrollback.push_back(line.clone());
+    while room.scrollback.len() > room.scrollback_size {
+        room.scrollback.pop_front();
+    }
+    let members: Vec<u64> = room.members.keys().copied().filter(|&id| Some(id) != except).collect();
+    drop(room);
+    for id in members {
+        sessions::send(id, Control::Chat(line.clone()));
+    }
+}
+
+fn timestamp() -> String {
+    Local::now().format("%H:%M").to_string().dark_grey().to_string()
+}
+
+fn notice(text: &str) -> String {
+    format!("{} {}", timestamp(), format!("-- {}", text).dark_grey())
+}
+
+// Nicknames are compared without regard to case, like account names
+fn taken(room: &Room, id: u64, nick: &str) -> bool {
+    room.members.iter().any(|(other, member)| *other != id && member.nick.eq_ignore_ascii_case(nick))
+}
+
+/// Puts a session in the room under a nickname, unless someone there already goes by it
+pub(crate) fn join(id: u64, nick: &str) -> Result<(), String> {
+    let mut room = room();
+    if taken(&room, id, nick) {
+        return Err(format!("Someone in the chat is already called {}.", nick));
+    }
+    room.members.insert(id, Member { nick: nick.to_string(), sent: VecDeque::new() });
+    tracing::info!(session = id, nick, "Joined chat");
+    // The reader joining sees it with the rest of the scrollback
+    announce(room, notice(&format!("{} joined", nick)), Some(id));
+    Ok(())
+}
+
+/// Held by a connection for as long as it's open, taking its session out of the room when it
+/// closes, however that happens. It isn't Clone, so copies of a handler can't make a reader leave
+pub(crate) struct ChatMembership(u64);
+
+impl ChatMembership {
+    pub fn new(id: u64) -> Self {
+        ChatMembership(id)
+    }
+}
+
+impl Drop for ChatMembership {
+    fn drop(&mut self) {
+        leave(self.0);
+    }
+}
+
+/// Takes a session out of the room, if it's there
+pub(crate) fn leave(id: u64) {
+    let mut room = room();
+    let Some(member) = room.members.remove(&id) else {
+        return;
+    };
+    tracing::info!(session = id, nick = %member.nick, "Left chat");
+    announce(room, notice(&format!("{} left", member.nick)), None);
+}
+
+pub(crate) fn rename(id: u64, nick: &str) -> Result<(), String> {
+    let mut room = room();
+    if taken(&room, id, nick) {
+        return Err(format!("Someone in the chat is already called {}.", nick));
+    }
+    let Some(member) = room.members.get_mut(&id) else {
+        return Err("You're not in the chat.".to_string());
+    };
+    let old = std::mem::replace(&mut member.nick, nick.to_string());
+    announce(room, notice(&format!("{} is now {}", old, nick)), None);
+    Ok(())
+}
+
+/// Sends a message to the room as the session's nickname
+pub(crate) fn say(id: u64, text: &str) -> Result<(), String> {
+    post(id, text, false)
+}
+
+/// Sends a "/me" message, shown as the nickname doing something
+pub(crate) fn act(id: u64, text: &str) -> Result<(), String> {
+    post(id, text, true)
+}
+
+fn post(id: u64, text: &str, action: bool) -> Result<(), String> {
+    let text: String = ansi::printable(text).trim().chars().take(MAX_MESSAGE).collect();
+    if text.is_empty() {
+        return Ok(());
+    }
+    let mut room = room();
+    let limit = room.messages_per_minute;
+    let Some(member) = room.members.get_mut(&id) else {
+        return Err("You're not in the chat.".to_string());
+    };
+    let now = Instant::now();
+    while member.sent.front().is_some_and(|sent| now.duration_since(*sent) >= RATE_WINDOW) {
+        member.sent.pop_front();
+    }
+    if member.sent.len() >= limit {
+        return Err("Slow down a little, that's a lot of messages in a minute.".to_string());
+    }
+    member.sent.push_back(now);
+    let line = if action {
+        format!("{} * {} {}", timestamp(), member.nick.as_str().bold(), text)
+    } else {
+        format!("{} {} {}", timestamp(), format!("<{}>", member.nick).bold(), text)
+    };
+    announce(room, line, None);
+    Ok(())
+}
+
+/// Nicknames of everyone in the room, alphabetically
+pub(crate) fn who() -> Vec<String> {
+    let mut nicks: Vec<String> = room().members.values().map(|member| member.nick.clone()).collect();
+    nicks.sort_by_key(|nick| nick.to_lowercase());
+    nicks
+}
+
+pub(crate) fn members() -> Vec<u64> {
+    room().members.keys().copied().collect()
+}
+
+pub(crate) fn is_member(id: u64) -> bool {
+    room().members.contains_key(&id)
+}
+
+/// The most recent lines said in the room, oldest first
+pub(crate) fn scrollback(lines: usize) -> Vec<String> {
+    let room = room();
+    room.scrollback.iter().skip(room.scrollback.len().saturating_sub(lines)).cloned().collect()
+}
@@ -0,0 +1,162 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::Duration;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ansi;
+use crate::config::CommentsConfig;
+
+/// Longest comment a reader can leave, in characters and in lines
+pub const MAX_COMMENT: usize = 500;
+pub const MAX_LINES: usize = 8;
+// How often changes are written out; the last few seconds of them are lost if the server is killed
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+static STORE: LazyLock<Mutex<Store>> = LazyLock::new(|| Mutex::new(Store::default()));
+
+// Define the Comment struct for one reader's comment on a section
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Comment {
+    pub id: u64,
+    // The section's directory, which stays put when the magazine is reloaded
+    pub section: String,
+    pub author: String,
+    pub text: String,
+    // RFC 3339
+    pub posted: String,
+    // Only approved comments are shown to readers
+    pub approved: bool,
+}
+
+// Every comment, pending and approved, in the order they came in
+#[derive(Default, Deserialize, Serialize)]
+struct Store {
+    #[serde(skip)]
+    moderated: bool,
+    // Changed since it was last written out
+    #[serde(skip)]
+    dirty: bool,
+    next_id: u64,
+    comments: Vec<Comment>,
+}
+
+fn store() -> MutexGuard<'static, Store> {
+    STORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Loads the comments file when one is configured, comments are kept in memory only otherwise
+pub fn init(config: &CommentsConfig) -> Result<()> {
+    let mut loaded = match &config.store {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str::<Store>(&text)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse comments file {:?}: {}", path, e)))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Store::default(),
+            Err(e) => return Err(Error::new(e.kind(), format!("Failed to read comments file {:?}: {}", path, e))),
+        },
+        None => Store::default(),
+    };
+    loaded.moderated = config.moderate;
+    *store() = loaded;
+    if let Some(path) = &config.store {
+        tokio::spawn(save_periodically(path.clone()));
+    }
+    Ok(())
+}
+
+// Every few seconds, write the store out if anything changed. It's serialized under the lock
+// but written on the blocking pool, so posting and moderating never wait for the disk
+async fn save_periodically(path: PathBuf) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let json = {
+            let mut store = store();
+            if !std::mem::take(&mut store.dirty) {
+                continue;
+            }
+            serde_json::to_vec_pretty(&*store)
+        };
+        let destination = path.clone();
+        let written = tokio::task::spawn_blocking(move || write(&destination, json.map_err(Error::other)?))
+            .await
+            .unwrap_or_else(|e| Err(Error::other(e)));
+        if let Err(e) = written {
+            tracing::warn!("Failed to save comments to {:?}: {}", path, e);
+            store().dirty = true;
+        }
+    }
+}
+
+// Write the store out whole, through a temporary file so a crash never leaves half of it
+fn write(path: &Path, json: Vec<u8>) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, json)?;
+    fs::rename(&temporary, path)
+}
+
+/// What a reader typed, made safe to show other readers (see `ansi::printable`) and at most MAX_LINES lines
+pub(crate) fn sanitize(text: &str) -> String {
+    let lines: Vec<String> = text.lines().map(|line| ansi::printable(line).trim_end().to_string()).collect();
+    let text = lines.join("\n");
+    text.trim().lines().take(MAX_LINES).collect::<Vec<_>>().join("\n").chars().take(MAX_COMMENT).collect()
+}
+
+/// Adds a comment, held for approval when comments are moderated, returning whether it shows right away
+pub(crate) fn post(section: &str, author: &str, text: &str) -> bool {
+    let mut store = store();
+    store.next_id += 1;
+    let comment = Comment {
+        id: store.next_id,
+        section: section.to_string(),
+        author: author.to_string(),
+        text: sanitize(text),
+        posted: Utc::now().to_rfc3339(),
+        approved: !store.moderated,
+    };
+    tracing::info!(id = comment.id, section, author, "Comment posted");
+    let approved = comment.approved;
+    store.comments.push(comment);
+    store.dirty = true;
+    approved
+}
+
+/// Whether new comments wait for a moderator
+pub(crate) fn moderated() -> bool {
+    store().moderated
+}
+
+/// Approved comments on a section, oldest first
+pub(crate) fn approved(section: &str) -> Vec<Comment> {
+    store().comments.iter().filter(|comment| comment.approved && comment.section == section).cloned().collect()
+}
+
+/// Comments waiting for a moderator, oldest first
+pub(crate) fn pending() -> Vec<Comment> {
+    store().comments.iter().filter(|comment| !comment.approved).cloned().collect()
+}
+
+/// Shows a pending comment to readers, false when there's no such comment waiting
+pub(crate) fn approve(id: u64) -> bool {
+    let mut store = store();
+    let Some(comment) = store.comments.iter_mut().find(|comment| comment.id == id && !comment.approved) else {
+        return false;
+    };
+    comment.approved = true;
+    store.dirty = true;
+    true
+}
+
+/// Deletes a comment, pending or approved, false when there's no such comment
+pub(crate) fn reject(id: u64) -> bool {
+    let mut store = store();
+    let before = store.comments.len();
+    store.comments.retain(|comment| comment.id != id);
+    if store.comments.len() == before {
+        return false;
+    }
+    store.dirty = true;
+    true
+}
@@ -0,0 +1,474 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::helpers::parse_color;
+use crate::keymap::{Keymap, KeymapConfig};
+use crate::proxy_protocol::{Cidr, ProxyProtocol, ProxySettings};
+
+// Environment variables starting with this prefix override config keys, with "__" between
+// section and key, e.g. TELZINE_LISTEN__PORT=2323 or TELZINE_LOGGING__LEVEL=debug
+const ENV_PREFIX: &str = "TELZINE_";
+
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+const LOG_FORMATS: [&str; 2] = ["text", "json"];
+
+/// Effective server configuration: built-in defaults, then the config file, then
+/// environment variables, then command line options
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Magazine directory to serve
+    pub magazine: PathBuf,
+    /// Directories searched for `magazine` when it is a relative path that doesn't exist as given
+    pub library: Vec<PathBuf>,
+    pub listen: ListenConfig,
+    pub limits: LimitsConfig,
+    pub timeouts: TimeoutsConfig,
+    pub logging: LoggingConfig,
+    pub recording: RecordingConfig,
+    pub admin: AdminConfig,
+    pub finger: FingerConfig,
+    pub bookmarks: BookmarksConfig,
+    pub accounts: AccountsConfig,
+    pub comments: CommentsConfig,
+    pub chat: ChatConfig,
+    pub theme: ThemeConfig,
+    pub keymap: KeymapConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub ip: Vec<String>,
+    pub port: u16,
+    pub unix_sockets: Vec<PathBuf>,
+    pub listen_fds: bool,
+    pub proxy_protocol: ProxyProtocol,
+    /// Load balancers (addresses or CIDR blocks) whose PROXY headers are believed, any peer when empty
+    pub trusted_proxies: Vec<String>,
+    pub tls_port: Option<u16>,
+    pub tls_cert: PathBuf,
+    pub tls_key: PathBuf,
+    pub ssh_port: Option<u16>,
+    pub ssh_host_key: PathBuf,
+    pub http_port: Option<u16>,
+    /// Port for the Gopher frontend, off when unset
+    pub gopher_port: Option<u16>,
+    /// Host name Gopher menus send clients back to, which must be how readers reach this server
+    pub gopher_host: String,
+    /// Port for the finger responder, off when unset
+    pub finger_port: Option<u16>,
+    /// Port for the Gemini frontend, off when unset
+    pub gemini_port: Option<u16>,
+    /// Host name the generated Gemini certificate is issued for
+    pub gemini_host: String,
+    /// Gemini certificate and key, a self-signed pair is generated when neither exists
+    pub gemini_cert: PathBuf,
+    pub gemini_key: PathBuf,
+    /// Port for the Prometheus /metrics endpoint, off when unset
+    pub metrics_port: Option<u16>,
+    /// Address the metrics endpoint binds, loopback by default so it isn't exposed to readers
+    pub metrics_ip: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Readers allowed at once across every frontend, unlimited when unset
+    pub max_connections: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    /// Seconds without input before a reader is disconnected, never when unset
+    pub idle_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Least severe server log level shown: error, warn, info, debug or trace
+    pub level: String,
+    /// Server log format, "text" or "json"
+    pub format: String,
+    /// File the server log is appended to instead of stderr
+    pub file: Option<PathBuf>,
+    /// File session connects, page views and disconnects are appended to as JSON lines, "-" for stdout
+    pub access_log: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Directory every session is recorded to as an asciicast v2 file, off when unset
+    pub directory: Option<PathBuf>,
+    /// Also record what readers type
+    pub input: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FingerConfig {
+    /// Handlebars template answered to finger queries, a built-in summary of the issue when unset
+    pub plan: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BookmarksConfig {
+    /// JSON file readers' bookmarks, last positions and handles are kept in, in memory only when unset
+    pub store: Option<PathBuf>,
+    /// Forget readers who haven't been back in this many days
+    pub keep_days: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Embedded database reader accounts are kept in, accounts are off when unset
+    pub database: Option<PathBuf>,
+    /// Let readers in without an account
+    pub guests: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommentsConfig {
+    /// JSON file readers' comments are kept in, in memory only when unset
+    pub store: Option<PathBuf>,
+    /// Hold new comments until they're approved from the admin console
+    pub moderate: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// Let readers talk to each other in a chat room off the front page, off by default
+    pub enabled: bool,
+    /// Messages kept for readers who join later
+    pub scrollback: usize,
+    /// Messages one reader can send in a minute before they're asked to slow down
+    pub messages_per_minute: usize,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Unix socket the admin console listens on, only usable by the user running telzine; off when unset
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Background color pages are drawn on
+    pub background: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            magazine: PathBuf::from("./examples/example-zine"),
+            library: Vec::new(),
+            listen: ListenConfig::default(),
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            logging: LoggingConfig::default(),
+            recording: RecordingConfig::default(),
+            admin: AdminConfig::default(),
+            finger: FingerConfig::default(),
+            bookmarks: BookmarksConfig::default(),
+            accounts: AccountsConfig::default(),
+            comments: CommentsConfig::default(),
+            chat: ChatConfig::default(),
+            theme: ThemeConfig::default(),
+            keymap: KeymapConfig { preset: Some("default".to_string()), ..KeymapConfig::default() },
+        }
+    }
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            ip: vec!["127.0.0.1".to_string()],
+            port: 8080,
+            unix_sockets: Vec::new(),
+            listen_fds: false,
+            proxy_protocol: ProxyProtocol::Off,
+            trusted_proxies: Vec::new(),
+            tls_port: None,
+            tls_cert: PathBuf::from("./cert.pem"),
+            tls_key: PathBuf::from("./key.pem"),
+            ssh_port: None,
+            ssh_host_key: PathBuf::from("./ssh_host_ed25519_key"),
+            http_port: None,
+            gopher_port: None,
+            gopher_host: "localhost".to_string(),
+            finger_port: None,
+            gemini_port: None,
+            gemini_host: "localhost".to_string(),
+            gemini_cert: PathBuf::from("./gemini_cert.pem"),
+            gemini_key: PathBuf::from("./gemini_key.pem"),
+            metrics_port: None,
+            metrics_ip: "127.0.0.1".to_string(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: "text".to_string(),
+            file: None,
+            access_log: None,
+        }
+    }
+}
+
+impl Default for BookmarksConfig {
+    fn default() -> Self {
+        BookmarksConfig { store: None, keep_days: 180 }
+    }
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig { database: None, guests: true }
+    }
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        ChatConfig { enabled: false, scrollback: 100, messages_per_minute: 20 }
+    }
+}
+
+impl Default for CommentsConfig {
+    fn default() -> Self {
+        CommentsConfig { store: None, moderate: true }
+    }
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig { background: "black".to_string() }
+    }
+}
+
+impl Config {
+    /// Loads the defaults, overlaid with the config file (when given) and TELZINE_* environment variables
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let mut value = Value::try_from(Config::default()).map_err(|e| e.to_string())?;
+
+        if let Some(path) = path {
+            let text = fs::read_to_string(path).map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+            let file: Value = text.parse().map_err(|e| format!("Failed to parse config file {:?}: {}", path, e))?;
+            merge(&mut value, file);
+        }
+
+        let mut vars: Vec<(String, String)> = std::env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect();
+        vars.sort();
+        for (name, raw) in vars {
+            let keys: Vec<String> = name[ENV_PREFIX.len()..].split("__").map(|key| key.to_lowercase()).collect();
+            set_path(&mut value, &keys, parse_env_value(&raw)).map_err(|e| format!("{}: {}", name, e))?;
+        }
+
+        serde_path_to_error::deserialize(value).map_err(|e| format!("Invalid configuration: {}: {}", e.path(), e.inner().message()))
+    }
+
+    /// Checks the settings that the types alone can't, returning every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if !self.magazine_path().join("index.json").is_file() {
+            errors.push(format!("magazine: no index.json found in {:?} or the library", self.magazine));
+        }
+        for dir in &self.library {
+            if !dir.is_dir() {
+                errors.push(format!("library: {:?} is not a directory", dir));
+            }
+        }
+
+        let listen = &self.listen;
+        if listen.ip.is_empty() && !listen.listen_fds && listen.unix_sockets.is_empty() {
+            errors.push("listen: no ip, unix_sockets or listen_fds configured, nothing to listen on".to_string());
+        }
+        for cidr in &listen.trusted_proxies {
+            if let Err(e) = cidr.parse::<Cidr>() {
+                errors.push(format!("listen.trusted_proxies: {}", e));
+            }
+        }
+        let mut ports = vec![("port", Some(listen.port)), ("tls_port", listen.tls_port), ("ssh_port", listen.ssh_port), ("http_port", listen.http_port), ("gopher_port", listen.gopher_port), ("gemini_port", listen.gemini_port), ("finger_port", listen.finger_port), ("metrics_port", listen.metrics_port)];
+        if listen.listen_fds {
+            ports.remove(0);
+        }
+        for (i, (name, port)) in ports.iter().enumerate() {
+            if let Some((other, _)) = ports[..i].iter().find(|(_, p)| port.is_some() && p == port) {
+                errors.push(format!("listen.{}: port {} is already used by listen.{}", name, port.unwrap(), other));
+            }
+        }
+        if listen.tls_port.is_some() {
+            for (name, path) in [("tls_cert", &listen.tls_cert), ("tls_key", &listen.tls_key)] {
+                if !path.is_file() {
+                    errors.push(format!("listen.{}: {:?} does not exist", name, path));
+                }
+            }
+        }
+
+        if listen.gemini_port.is_some() && listen.gemini_cert.exists() != listen.gemini_key.exists() {
+            errors.push(format!(
+                "listen.gemini_cert/gemini_key: only one of {:?} and {:?} exists, provide both or remove it to generate a new pair",
+                listen.gemini_cert, listen.gemini_key
+            ));
+        }
+
+        if let Some(plan) = &self.finger.plan {
+            if !plan.is_file() {
+                errors.push(format!("finger.plan: {:?} does not exist", plan));
+            }
+        }
+
+        for (name, store) in [("bookmarks.store", &self.bookmarks.store), ("comments.store", &self.comments.store)] {
+            if let Some(parent) = store.as_ref().and_then(|store| store.parent()).filter(|parent| !parent.as_os_str().is_empty() && !parent.is_dir()) {
+                errors.push(format!("{}: directory {:?} does not exist", name, parent));
+            }
+        }
+
+        if self.bookmarks.keep_days == 0 {
+            errors.push("bookmarks.keep_days: must be at least 1".to_string());
+        }
+
+        match &self.accounts.database {
+            Some(database) => {
+                if let Some(parent) = database.parent().filter(|parent| !parent.as_os_str().is_empty() && !parent.is_dir()) {
+                    errors.push(format!("accounts.database: directory {:?} does not exist", parent));
+                }
+            }
+            None if !self.accounts.guests => {
+                errors.push("accounts.guests: turning guests off needs accounts.database, or nobody could get in".to_string());
+            }
+            None => {}
+        }
+
+        if self.chat.messages_per_minute == 0 {
+            errors.push("chat.messages_per_minute: must be at least 1, set chat.enabled = false to close the chat".to_string());
+        }
+
+        if self.limits.max_connections == Some(0) {
+            errors.push("limits.max_connections: must be at least 1, leave it unset for no limit".to_string());
+        }
+        if self.timeouts.idle_secs == Some(0) {
+            errors.push("timeouts.idle_secs: must be at least 1, leave it unset for no timeout".to_string());
+        }
+        if !LOG_LEVELS.contains(&self.logging.level.to_lowercase().as_str()) {
+            errors.push(format!("logging.level: {:?} is not one of {}", self.logging.level, LOG_LEVELS.join(", ")));
+        }
+        if !LOG_FORMATS.contains(&self.logging.format.to_lowercase().as_str()) {
+            errors.push(format!("logging.format: {:?} is not one of {}", self.logging.format, LOG_FORMATS.join(", ")));
+        }
+        if parse_color(&self.theme.background).is_none() {
+            errors.push(format!("theme.background: unknown color {:?}", self.theme.background));
+        }
+        match self.keymap() {
+            Ok(keymap) if keymap.quit.iter().all(|word| word.trim().is_empty()) => {
+                errors.push("keymap.quit: readers need at least one way to leave".to_string());
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(format!("keymap.preset: {}", e)),
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Settings that work but probably don't do what was meant
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.listen.proxy_protocol == ProxyProtocol::Optional && self.listen.trusted_proxies.is_empty() {
+            warnings.push(
+                "listen.proxy_protocol: optional believes a PROXY header from anyone, who can then pass for any address; \
+                 list your load balancers in listen.trusted_proxies"
+                    .to_string(),
+            );
+        }
+        if let Ok(keymap) = self.keymap() {
+            warnings.extend(keymap.conflicts().into_iter().map(|conflict| format!("keymap: {}", conflict)));
+        }
+        warnings
+    }
+
+    /// The PROXY protocol mode and the load balancers trusted to use it
+    pub fn proxy_settings(&self) -> ProxySettings {
+        // Checked by validate()
+        let trusted = self.listen.trusted_proxies.iter().filter_map(|cidr| cidr.parse::<Cidr>().ok()).collect();
+        ProxySettings::new(self.listen.proxy_protocol, trusted)
+    }
+
+    /// The server's keymap: the preset with any bindings given replacing its own
+    pub fn keymap(&self) -> Result<Keymap, String> {
+        self.keymap.apply(&Keymap::default())
+    }
+
+    /// Resolves `magazine` against the library directories
+    pub fn magazine_path(&self) -> PathBuf {
+        if self.magazine.is_relative() && !self.magazine.exists() {
+            if let Some(found) = self.library.iter().map(|dir| dir.join(&self.magazine)).find(|path| path.is_dir()) {
+                return found;
+            }
+        }
+        self.magazine.clone()
+    }
+
+    /// The effective configuration as TOML, for `telzine config dump`
+    pub fn dump(&self) -> String {
+        toml::to_string_pretty(self).unwrap_or_else(|e| panic!("Failed to serialize configuration: {}", e))
+    }
+}
+
+// Overlay `other` onto `base`, recursing into tables so a file only needs the keys it changes
+fn merge(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Table(base), Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+fn set_path(value: &mut Value, keys: &[String], new: Value) -> Result<(), String> {
+    let Some((last, parents)) = keys.split_last() else {
+        return Err("empty key".to_string());
+    };
+    let mut table = value.as_table_mut().ok_or("configuration is not a table")?;
+    for key in parents {
+        table = table
+            .get_mut(key)
+            .and_then(Value::as_table_mut)
+            .ok_or_else(|| format!("unknown config section {:?}", key))?;
+    }
+    table.insert(last.clone(), new);
+    Ok(())
+}
+
+// Environment values are TOML literals (2323, true, ["::", "0.0.0.0"]) or otherwise plain strings
+fn parse_env_value(raw: &str) -> Value {
+    format!("value = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
@@ -0,0 +1,412 @@
+use std::fs;
+use std::io::{Error, Result};
+use std::path::Path;
+use std::str::FromStr;
+use chrono::Local;
+use crossterm::style::Color;
+
+use crate::ansi::{self, Rgb, Style};
+use crate::zine::{Magazine, Section};
+
+// Foreground used where a page doesn't set one, picked to stay readable on the theme background
+const LIGHT_TEXT: Rgb = (229, 229, 229);
+const DARK_TEXT: Rgb = (0, 0, 0);
+
+// Width of the table of contents in the single file exports, and the width recorded in SAUCE
+const COLUMNS: usize = 80;
+// The font recorded in SAUCE for the .ans export
+const SAUCE_FONT: &[u8] = b"IBM VGA";
+
+// Formats a magazine can be exported to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    // A static site, one file per page
+    Html,
+    // The whole issue as one plain text file with the styling stripped
+    Text,
+    // The whole issue as one CP437 ANSI art file with the styling kept and a SAUCE record
+    Ans,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "html" => Ok(ExportFormat::Html),
+            "text" | "txt" => Ok(ExportFormat::Text),
+            "ans" | "ansi" => Ok(ExportFormat::Ans),
+            _ => Err(format!("Invalid export format {:?}, expected html, text or ans", s)),
+        }
+    }
+}
+
+/// Writes the magazine to the `out` directory in the given format, creating it if needed
+pub fn run(magazine: &Magazine, format: ExportFormat, background: Color, out: &Path) -> Result<()> {
+    fs::create_dir_all(out).map_err(|e| Error::new(e.kind(), format!("Failed to create export directory {:?}: {}", out, e)))?;
+    match format {
+        ExportFormat::Html => Html::new(magazine, background).write(out),
+        ExportFormat::Text => fs::write(out.join(format!("{}.txt", slug(&magazine.title))), text(magazine)),
+        ExportFormat::Ans => fs::write(out.join(format!("{}.ans", slug(&magazine.title))), ans(magazine, background)),
+    }
+}
+
+// The pages of the issue in reading order, each with the heading it's filed under in the table of contents
+fn issue_pages(magazine: &Magazine) -> Vec<(String, &str)> {
+    let mut pages = vec![("Cover".to_string(), magazine.cover_text.as_str()), ("Front page".to_string(), magazine.front_text.as_str())];
+    for (index, section) in magazine.all_sections().into_iter().enumerate() {
+        for (page, content) in section.all_pages().into_iter().enumerate() {
+            let heading = format!("{}. {} by {}, page {} of {}", index + 1, section.title, section.author, page + 1, section.pages.len());
+            pages.push((heading, content.text.as_str()));
+        }
+    }
+    pages
+}
+
+// The title and a table of contents giving the page each part starts on, counting this as page 1
+fn table_of_contents(magazine: &Magazine) -> String {
+    let mut entries = vec![("Cover".to_string(), Some(2)), ("Front page".to_string(), Some(3))];
+    let mut next_page = 4;
+    for (index, section) in magazine.all_sections().into_iter().enumerate() {
+        let start = (!section.pages.is_empty()).then_some(next_page);
+        entries.push((format!("{}. {} by {}", index + 1, section.title, section.author), start));
+        next_page += section.pages.len();
+    }
+
+    let mut toc = format!("{}\n{}\n\nContents\n\n", magazine.title, "=".repeat(magazine.title.chars().count()));
+    for (entry, page) in entries {
+        let page = page.map(|page| page.to_string()).unwrap_or_else(|| "-".to_string());
+        let dots = COLUMNS.saturating_sub(entry.chars().count() + page.len() + 6).max(3);
+        toc.push_str(&format!("  {} {} {}\n", entry, ".".repeat(dots), page));
+    }
+    toc
+}
+
+// Plain text with a form feed starting each page, as printers and pagers expect
+fn text(magazine: &Magazine) -> String {
+    let mut text = table_of_contents(magazine);
+    for (heading, content) in issue_pages(magazine) {
+        text.push_str(&format!("\x0c{}\n\n{}\n", heading, ansi::strip(content).trim_end()));
+    }
+    text
+}
+
+// The issue as classic ANSI art, the kind BBS viewers draw: CP437 text with CRLF line endings,
+// every run restyled from scratch in the 16 color palette on the theme background, and a SAUCE
+// record so BBS file areas can describe it
+fn ans(magazine: &Magazine, background: Color) -> Vec<u8> {
+    let background = ansi::color_rgb(background).map(|rgb| nearest(rgb, 8));
+    // Viewers draw unstyled text light gray, which would vanish on a light gray background
+    let foreground = (background == Some(7)).then_some(0);
+    let mut art = table_of_contents(magazine);
+    for (heading, content) in issue_pages(magazine) {
+        art.push_str(&format!("\n\x1b[0;1m{}\x1b[0m\n\n", heading));
+        for (style, text) in ansi::segments(content.trim_end()) {
+            art.push_str(&sgr(&style, foreground, background));
+            art.push_str(&text);
+        }
+        art.push_str("\x1b[0m\n");
+    }
+    let art = art.replace('\n', "\r\n");
+    let lines = art.matches('\n').count();
+    let mut bytes: Vec<u8> = art.chars().map(cp437).collect();
+    bytes.extend(sauce(magazine, bytes.len(), lines));
+    bytes
+}
+
+// The escape sequence that draws text in this style, starting from a reset. Classic ANSI has
+// eight colors and bold, which brightens the foreground; italics and underlines are dropped
+fn sgr(style: &Style, foreground: Option<usize>, background: Option<usize>) -> String {
+    let fg = style.fg.map(|rgb| nearest(rgb, 16)).or(foreground);
+    let mut codes = vec!["0".to_string()];
+    if style.bold || fg.is_some_and(|fg| fg >= 8) {
+        codes.push("1".to_string());
+    }
+    if let Some(fg) = fg {
+        codes.push((30 + fg % 8).to_string());
+    }
+    if let Some(bg) = style.bg.map(|rgb| nearest(rgb, 8)).or(background) {
+        codes.push((40 + bg).to_string());
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+// The closest of the first `colors` palette entries, by squared distance
+fn nearest((r, g, b): Rgb, colors: u8) -> usize {
+    let distance = |index: u8| {
+        let (pr, pg, pb) = ansi::palette(index);
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    (0..colors).min_by_key(|&index| distance(index)).unwrap_or(0) as usize
+}
+
+// The upper half of code page 437, 0x80 to 0xFF
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+// A character as its CP437 byte, '?' for anything the code page doesn't have
+fn cp437(c: char) -> u8 {
+    if c.is_ascii() {
+        return c as u8;
+    }
+    CP437_HIGH.chars().position(|high| high == c).map_or(b'?', |index| 0x80 + index as u8)
+}
+
+// The SAUCE trailer: an end of file marker, a comment block listing every section and its
+// author, then the 128 byte record itself. See https://www.acid.org/info/sauce/sauce.htm
+fn sauce(magazine: &Magazine, file_size: usize, lines: usize) -> Vec<u8> {
+    let sections = magazine.all_sections();
+    let mut authors: Vec<&str> = Vec::new();
+    for section in &sections {
+        if !authors.contains(&section.author.as_str()) {
+            authors.push(&section.author);
+        }
+    }
+    let comments: Vec<String> = sections.iter().take(255).map(|section| format!("{} by {}", section.title, section.author)).collect();
+
+    let mut trailer = vec![0x1a];
+    if !comments.is_empty() {
+        trailer.extend(b"COMNT");
+        for comment in &comments {
+            trailer.extend(sauce_field(comment, 64));
+        }
+    }
+    trailer.extend(b"SAUCE00");
+    trailer.extend(sauce_field(&magazine.title, 35));
+    trailer.extend(sauce_field(&authors.join(", "), 20));
+    trailer.extend(sauce_field("", 20));
+    trailer.extend(Local::now().format("%Y%m%d").to_string().into_bytes());
+    trailer.extend((file_size as u32).to_le_bytes());
+    // Data type 1 (character) and file type 1 (ANSi)
+    trailer.extend([1, 1]);
+    trailer.extend((COLUMNS as u16).to_le_bytes());
+    trailer.extend((lines.min(u16::MAX as usize) as u16).to_le_bytes());
+    trailer.extend([0; 4]);
+    trailer.push(comments.len() as u8);
+    // No flags: bold brightens the foreground and blink is never used for bright backgrounds.
+    // The font is the one CP437 art is drawn in, padded with NULs
+    trailer.push(0);
+    let mut font = SAUCE_FONT.to_vec();
+    font.resize(22, 0);
+    trailer.extend(font);
+    trailer
+}
+
+// SAUCE fields are fixed width and space padded, in CP437 like the art itself
+fn sauce_field(text: &str, width: usize) -> Vec<u8> {
+    let mut field: Vec<u8> = text.chars().take(width).map(|c| if c.is_control() { b'?' } else { cp437(c) }).collect();
+    field.resize(width, b' ');
+    field
+}
+
+// File name for a single file export: "Telzine Issue #1" is telzine-issue-1
+fn slug(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() { "magazine".to_string() } else { slug }
+}
+
+// Define the Html struct that lays the magazine out as a static site: the cover at index.html,
+// the front page with the table of contents, an index per section and one file per page
+struct Html<'a> {
+    magazine: &'a Magazine,
+    background: Rgb,
+}
+
+impl<'a> Html<'a> {
+    fn new(magazine: &'a Magazine, background: Color) -> Self {
+        Html { magazine, background: ansi::color_rgb(background).unwrap_or(DARK_TEXT) }
+    }
+
+    fn write(&self, out: &Path) -> Result<()> {
+        fs::write(out.join("style.css"), self.stylesheet())?;
+
+        let cover = format!("{}\n<nav><a href=\"front.html\">Open the magazine &rarr;</a></nav>\n", pre(&self.magazine.cover_text));
+        fs::write(out.join("index.html"), self.document(&self.magazine.title, &cover))?;
+
+        let mut front = pre(&self.magazine.front_text);
+        front.push_str("\n<nav>\n<ol>\n");
+        for (index, section) in self.magazine.all_sections().into_iter().enumerate() {
+            front.push_str(&format!(
+                "<li><a href=\"{}\">{}</a> by {}</li>\n",
+                section_file(index),
+                escape(&section.title),
+                escape(&section.author)
+            ));
+        }
+        front.push_str("</ol>\n<a href=\"index.html\">Cover</a>\n</nav>\n");
+        fs::write(out.join("front.html"), self.document(&self.magazine.title, &front))?;
+
+        let sections = self.magazine.all_sections();
+        for (index, section) in sections.iter().enumerate() {
+            fs::write(out.join(section_file(index)), self.section_index(index, section))?;
+            for page in 0..section.pages.len() {
+                fs::write(out.join(page_file(index, page)), self.page(&sections, index, page))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn section_index(&self, index: usize, section: &Section) -> String {
+        let mut body = format!("<h1>{}</h1>\n<p>by {}</p>\n<nav>\n<ol>\n", escape(&section.title), escape(&section.author));
+        for (page, content) in section.all_pages().into_iter().enumerate() {
+            body.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", page_file(index, page), escape(&content.display_title())));
+        }
+        body.push_str("</ol>\n<a href=\"front.html\">Contents</a>\n</nav>\n");
+        self.document(&format!("{} - {}", section.title, self.magazine.title), &body)
+    }
+
+    // Previous and next run straight through the magazine, crossing into the neighbouring
+    // sections and back to the contents at either end
+    fn page(&self, sections: &[&Section], index: usize, page: usize) -> String {
+        let section = sections[index];
+        let previous = if page > 0 {
+            page_file(index, page - 1)
+        } else if index > 0 && !sections[index - 1].pages.is_empty() {
+            page_file(index - 1, sections[index - 1].pages.len() - 1)
+        } else {
+            "front.html".to_string()
+        };
+        let next = if page + 1 < section.pages.len() {
+            page_file(index, page + 1)
+        } else {
+            match sections.get(index + 1) {
+                Some(following) if !following.pages.is_empty() => page_file(index + 1, 0),
+                Some(_) => section_file(index + 1),
+                None => "front.html".to_string(),
+            }
+        };
+        let body = format!(
+            "{}\n<nav><a href=\"{}\" rel=\"prev\">&larr; Previous</a> <a href=\"{}\">{}</a> <a href=\"front.html\">Contents</a> <a href=\"{}\" rel=\"next\">Next &rarr;</a></nav>\n",
+            pre(&section.pages[page].text),
+            previous,
+            section_file(index),
+            escape(&section.title),
+            next
+        );
+        self.document(&format!("{} page {} - {}", section.title, page + 1, self.magazine.title), &body)
+    }
+
+    fn document(&self, title: &str, body: &str) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<link rel=\"stylesheet\" href=\"style.css\">\n</head>\n<body>\n{}</body>\n</html>\n",
+            escape(title),
+            body
+        )
+    }
+
+    fn stylesheet(&self) -> String {
+        let (r, g, b) = self.background;
+        // Perceived brightness, so a light theme background gets dark text
+        let light = r as u32 * 299 + g as u32 * 587 + b as u32 * 114 > 128_000;
+        let text = if light { DARK_TEXT } else { LIGHT_TEXT };
+        format!(
+            "body {{ background: {bg}; color: {fg}; font-family: monospace; margin: 2em; }}\n\
+             pre {{ font-family: inherit; line-height: 1.2; white-space: pre; overflow-x: auto; }}\n\
+             a {{ color: inherit; }}\n\
+             nav {{ margin-top: 1em; }}\n\
+             nav a {{ margin-right: 1em; }}\n",
+            bg = hex(self.background),
+            fg = hex(text)
+        )
+    }
+}
+
+// A rendered page as preformatted HTML, each run of styled text in a span
+fn pre(text: &str) -> String {
+    let mut html = String::from("<pre>");
+    for (style, text) in ansi::segments(text) {
+        let css = css(&style);
+        if css.is_empty() {
+            html.push_str(&escape(&text));
+        } else {
+            html.push_str(&format!("<span style=\"{}\">{}</span>", css, escape(&text)));
+        }
+    }
+    html.push_str("</pre>");
+    html
+}
+
+fn css(style: &Style) -> String {
+    let mut rules = Vec::new();
+    if let Some(fg) = style.fg {
+        rules.push(format!("color:{}", hex(fg)));
+    }
+    if let Some(bg) = style.bg {
+        rules.push(format!("background:{}", hex(bg)));
+    }
+    if style.bold {
+        rules.push("font-weight:bold".to_string());
+    }
+    if style.italic {
+        rules.push("font-style:italic".to_string());
+    }
+    if style.underline {
+        match style.underline_color {
+            Some(color) => rules.push(format!("text-decoration:underline {}", hex(color))),
+            None => rules.push("text-decoration:underline".to_string()),
+        }
+    }
+    rules.join(";")
+}
+
+fn hex((r, g, b): Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn section_file(section: usize) -> String {
+    format!("section-{}.html", section + 1)
+}
+
+fn page_file(section: usize, page: usize) -> String {
+    format!("section-{}-page-{}.html", section + 1, page + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sauce_record() {
+        let magazine = Magazine::try_from_directory(Path::new("examples/example-zine")).unwrap();
+        let trailer = sauce(&magazine, 1234, 56);
+        let sections = magazine.all_sections().len();
+        assert_eq!(trailer[0], 0x1a);
+        assert_eq!(&trailer[1..6], b"COMNT");
+        assert_eq!(trailer.len(), 1 + 5 + 64 * sections + 128);
+
+        let record = &trailer[trailer.len() - 128..];
+        assert_eq!(&record[..7], b"SAUCE00");
+        assert_eq!(u32::from_le_bytes(record[90..94].try_into().unwrap()), 1234);
+        assert_eq!(&record[94..96], &[1, 1]);
+        assert_eq!(u16::from_le_bytes([record[96], record[97]]), COLUMNS as u16);
+        assert_eq!(u16::from_le_bytes([record[98], record[99]]), 56);
+        assert_eq!(record[104] as usize, sections);
+        assert_eq!(record[105], 0);
+        assert_eq!(&record[106..113], b"IBM VGA");
+        assert!(record[113..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn ans_is_cp437_with_classic_colors() {
+        assert_eq!(cp437('A'), b'A');
+        assert_eq!(cp437('é'), 0x82);
+        assert_eq!(cp437('█'), 0xdb);
+        assert_eq!(cp437('\u{a0}'), 0xff);
+        assert_eq!(cp437('€'), b'?');
+        assert_eq!(sauce_field("Café\n", 6), b"Caf\x82? ");
+
+        let style = Style { fg: Some((255, 0, 0)), bg: Some((0, 0, 200)), italic: true, ..Style::default() };
+        assert_eq!(sgr(&style, None, Some(0)), "\x1b[0;1;31;44m");
+        assert_eq!(sgr(&Style::default(), Some(0), Some(7)), "\x1b[0;30;47m");
+        assert_eq!(sgr(&Style::default(), None, None), "\x1b[0m");
+    }
+}
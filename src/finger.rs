@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use handlebars::Handlebars;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::ansi;
+use crate::helpers::register_helpers;
+use crate::metrics;
+use crate::sessions;
+use crate::zine_handler::Shelf;
+
+// Finger queries are a user name at most, anything longer is not a finger client
+const MAX_QUERY: u64 = 512;
+// How long a client gets to send its query
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The plan when none is configured
+const DEFAULT_PLAN: &str = "\
+{{title}}
+
+{{#each sections}}
+  {{add @index 1}}. {{title}} by {{author}}
+{{/each}}
+
+{{readers}} reading now.
+";
+
+// Define the FingerServer struct that answers RFC 1288 queries with the magazine's plan
+pub(crate) struct FingerServer {
+    listeners: Vec<TcpListener>,
+    engine: Handlebars<'static>,
+    shelf: Shelf,
+    // The title of the newest issue in the library, None when that's the magazine on the shelf
+    latest: Option<String>,
+}
+
+impl FingerServer {
+    // Bind the finger listeners and compile the plan up front so mistakes in either surface at startup
+    pub async fn new(addrs: &[String], plan: Option<&Path>, shelf: Shelf, latest: Option<String>) -> Result<Self> {
+        let template = match plan {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| Error::new(e.kind(), format!("Failed to read finger plan {:?}: {}", path, e)))?,
+            None => DEFAULT_PLAN.to_string(),
+        };
+        let mut engine = Handlebars::new();
+        // The plan is plain text, not HTML
+        engine.register_escape_fn(handlebars::no_escape);
+        register_helpers(&mut engine);
+        engine
+            .register_template_string("plan", template)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid finger plan: {}", e)))?;
+
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            listeners.push(TcpListener::bind(addr).await?);
+        }
+        Ok(FingerServer { listeners, engine, shelf, latest })
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let server = Arc::new((self.engine, self.shelf, self.latest));
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            info!("Listening for finger on: {}", listener.local_addr()?);
+            accept_loops.spawn(accept_loop(listener, server.clone()));
+        }
+        // Accept loops only end on a listener error, which takes the whole server down
+        while let Some(result) = accept_loops.join_next().await {
+            result.map_err(Error::other)??;
+        }
+        Ok(())
+    }
+}
+
+async fn accept_loop(listener: TcpListener, server: Arc<(Handlebars<'static>, Shelf, Option<String>)>) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            let (engine, shelf, latest) = &*server;
+            if let Err(e) = serve(stream, peer, engine, shelf, latest.as_deref()).await {
+                warn!(%peer, "Error handling finger query: {}", e);
+            }
+        });
+    }
+}
+
+// Answer one query with the plan, whoever was asked for: the magazine is the only user here
+async fn serve(mut stream: TcpStream, peer: SocketAddr, engine: &Handlebars<'_>, shelf: &Shelf, latest: Option<&str>) -> Result<()> {
+    if sessions::is_banned(peer.ip()) {
+        info!(%peer, "Refused banned address");
+        metrics::rejected("finger", "banned");
+        return Ok(());
+    }
+    let mut query = String::new();
+    let mut reader = BufReader::new(&mut stream).take(MAX_QUERY);
+    if timeout(REQUEST_TIMEOUT, reader.read_line(&mut query)).await.is_err() {
+        return Ok(());
+    }
+    debug!(%peer, query = query.trim(), "Finger query");
+
+    let plan = match render(engine, shelf, latest) {
+        Ok(plan) => plan,
+        Err(e) => {
+            warn!("Failed to render finger plan: {}", e);
+            "The plan could not be rendered.\r\n".to_string()
+        }
+    };
+    stream.write_all(plan.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// The plan for the magazine on the shelf right now, with the styling stripped and CRLF line endings
+fn render(engine: &Handlebars<'_>, shelf: &Shelf, latest: Option<&str>) -> std::result::Result<String, handlebars::RenderError> {
+    let magazine = shelf.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    let readers = sessions::list();
+    let mut frontends: BTreeMap<&str, usize> = BTreeMap::new();
+    for reader in &readers {
+        *frontends.entry(reader.frontend).or_default() += 1;
+    }
+    let context = json!({
+        "title": magazine.title.as_str(),
+        "sections": magazine.sections,
+        "vars": magazine.vars,
+        "pages": magazine.sections.iter().map(|section| section.pages.len()).sum::<usize>(),
+        "readers": readers.len(),
+        "frontends": frontends,
+        "latest": latest.unwrap_or(magazine.title.as_str()),
+    });
+    let plan = ansi::strip(&engine.render("plan", &context)?);
+    Ok(plan.replace("\r\n", "\n").replace('\n', "\r\n"))
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::{Error, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::ansi;
+use crate::metrics;
+use crate::sessions;
+use crate::telnet::tls_acceptor;
+use crate::zine::Magazine;
+use crate::zine_handler::Shelf;
+
+// Gemini requests are a URL of at most 1024 bytes plus CRLF
+const MAX_REQUEST: u64 = 1026;
+// How long a client gets to finish the handshake and send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Define the GeminiServer struct that serves the magazine as gemtext over TLS
+pub(crate) struct GeminiServer {
+    listeners: Vec<TcpListener>,
+    acceptor: TlsAcceptor,
+    shelf: Shelf,
+}
+
+impl GeminiServer {
+    // Bind the Gemini listeners up front so address errors surface at startup, generating a
+    // self-signed certificate for `host` if there isn't one yet
+    pub async fn new(addrs: &[String], cert_path: &Path, key_path: &Path, host: &str, shelf: Shelf) -> Result<Self> {
+        if !cert_path.exists() && !key_path.exists() {
+            generate_certificate(cert_path, key_path, host)?;
+        }
+        let acceptor = tls_acceptor(cert_path, key_path)?;
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            listeners.push(TcpListener::bind(addr).await?);
+        }
+        Ok(GeminiServer { listeners, acceptor, shelf })
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            info!("Listening for Gemini on: {}", listener.local_addr()?);
+            accept_loops.spawn(accept_loop(listener, self.acceptor.clone(), self.shelf.clone()));
+        }
+        // Accept loops only end on a listener error, which takes the whole server down
+        while let Some(result) = accept_loops.join_next().await {
+            result.map_err(Error::other)??;
+        }
+        Ok(())
+    }
+}
+
+// Gemini clients trust a server's certificate on first use, so a self-signed one is the norm.
+// It is kept on disk so returning readers see the same certificate.
+fn generate_certificate(cert_path: &Path, key_path: &Path, host: &str) -> Result<()> {
+    let certificate = rcgen::generate_simple_self_signed(vec![host.to_string()])
+        .map_err(|e| Error::other(format!("Failed to generate Gemini certificate: {}", e)))?;
+    let cert = certificate.serialize_pem().map_err(|e| Error::other(format!("Failed to encode Gemini certificate: {}", e)))?;
+    for path in [cert_path, key_path] {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(cert_path, cert)?;
+    fs::write(key_path, certificate.serialize_private_key_pem())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(key_path, fs::Permissions::from_mode(0o600))?;
+    }
+    info!("Generated new self-signed Gemini certificate for {} at {:?}", host, cert_path);
+    Ok(())
+}
+
+async fn accept_loop(listener: TcpListener, acceptor: TlsAcceptor, shelf: Shelf) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (acceptor, shelf) = (acceptor.clone(), shelf.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, peer, acceptor, &shelf).await {
+                warn!(%peer, "Error handling Gemini request: {}", e);
+            }
+        });
+    }
+}
+
+// Answer one request: finish the handshake, read the URL line, write the response and close
+async fn serve(stream: TcpStream, peer: SocketAddr, acceptor: TlsAcceptor, shelf: &Shelf) -> Result<()> {
+    if sessions::is_banned(peer.ip()) {
+        info!(%peer, "Refused banned address");
+        metrics::rejected("gemini", "banned");
+        return Ok(());
+    }
+    let request = timeout(REQUEST_TIMEOUT, async {
+        let mut stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!(%peer, "TLS handshake failed: {}", e);
+                metrics::rejected("gemini", "tls");
+                return None;
+            }
+        };
+        let mut line = String::new();
+        BufReader::new(&mut stream).take(MAX_REQUEST).read_line(&mut line).await.ok()?;
+        Some((stream, line))
+    });
+    // Too slow or a failed handshake, there is no one to answer
+    let Ok(Some((mut stream, line))) = request.await else {
+        return Ok(());
+    };
+    let url = line.trim_end_matches(['\r', '\n']);
+    debug!(%peer, url, "Gemini request");
+
+    let response = match request_path(url) {
+        Some(path) => {
+            let magazine = shelf.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+            respond(&magazine, path)
+        }
+        None => "59 Bad request\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// The path of a gemini:// URL, without any query or fragment
+fn request_path(url: &str) -> Option<&str> {
+    if url.len() > 1024 {
+        return None;
+    }
+    let rest = url.strip_prefix("gemini://")?;
+    let path = rest.find('/').map(|slash| &rest[slash..]).unwrap_or("/");
+    Some(path.split(['?', '#']).next().unwrap_or("/"))
+}
+
+// "/" is the front page, "/cover" the cover, "/<section>/" a section and "/<section>/<page>" a page
+fn respond(magazine: &Magazine, path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+    let numbers: Option<Vec<usize>> = parts.iter().map(|part| part.parse::<usize>().ok().filter(|&n| n > 0)).collect();
+    match (parts.as_slice(), numbers.as_deref()) {
+        ([], _) => gemtext(front_page(magazine)),
+        (["cover"], _) => gemtext(format!("# {}\n\n{}\n=> / Open the magazine\n", magazine.title, preformatted(&magazine.cover_text))),
+        (_, Some(&[section])) => match magazine.get_section(section - 1) {
+            Some(_) => gemtext(section_page(magazine, section)),
+            None => "51 No such section\r\n".to_string(),
+        },
+        (_, Some(&[section, page])) => match magazine.get_section(section - 1).and_then(|s| s.get_page(page - 1)) {
+            Some(_) => {
+                metrics::page_view(section, page);
+                gemtext(page_text(magazine, section, page))
+            }
+            None => "51 No such page\r\n".to_string(),
+        },
+        _ => "51 Not found\r\n".to_string(),
+    }
+}
+
+fn gemtext(body: String) -> String {
+    format!("20 text/gemini; charset=utf-8\r\n{}", body)
+}
+
+fn front_page(magazine: &Magazine) -> String {
+    let mut body = format!("# {}\n\n{}\n", magazine.title, preformatted(&magazine.front_text));
+    for (index, section) in magazine.all_sections().into_iter().enumerate() {
+        body.push_str(&format!("=> /{}/ {} by {}\n", index + 1, line_text(&section.title), line_text(&section.author)));
+    }
+    body.push_str("\n=> /cover Cover\n");
+    body
+}
+
+fn section_page(magazine: &Magazine, section: usize) -> String {
+    let current = &magazine.sections[section - 1];
+    let mut body = format!("# {}\n\nby {}\n\n", line_text(&current.title), line_text(&current.author));
+    for (page, content) in current.all_pages().into_iter().enumerate() {
+        body.push_str(&format!("=> /{}/{} {}\n", section, page + 1, line_text(&content.display_title())));
+    }
+    body.push_str(&format!("\n=> / Back to {}\n", line_text(&magazine.title)));
+    body
+}
+
+fn page_text(magazine: &Magazine, section: usize, page: usize) -> String {
+    let current = &magazine.sections[section - 1];
+    let mut body = format!(
+        "# {} - page {} of {}\n\n{}\n",
+        line_text(&current.title),
+        page,
+        current.pages.len(),
+        preformatted(&current.pages[page - 1].text)
+    );
+    if page > 1 {
+        body.push_str(&format!("=> /{}/{} Previous page\n", section, page - 1));
+    }
+    if page < current.pages.len() {
+        body.push_str(&format!("=> /{}/{} Next page\n", section, page + 1));
+    }
+    body.push_str(&format!("=> /{}/ {}\n=> / Contents\n", section, line_text(&current.title)));
+    body
+}
+
+// Rendered text as a preformatted block with the styling stripped. A line starting with
+// ``` would end the block early, so those are nudged over by a space.
+fn preformatted(content: &str) -> String {
+    let mut block = String::from("```\n");
+    for line in ansi::strip(content).trim_end().lines() {
+        if line.starts_with("```") {
+            block.push(' ');
+        }
+        block.push_str(line);
+        block.push('\n');
+    }
+    block.push_str("```\n");
+    block
+}
+
+// Titles and authors go on a single gemtext line
+fn line_text(text: &str) -> String {
+    ansi::strip(text).replace(['\r', '\n'], " ")
+}
@@ -0,0 +1,177 @@
+use std::io::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::ansi;
+use crate::metrics;
+use crate::sessions;
+use crate::zine::Magazine;
+use crate::zine_handler::Shelf;
+
+// Selectors are short paths, anything longer than this is not a Gopher client
+const MAX_SELECTOR: u64 = 1024;
+// How long a client gets to send its selector
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Define the GopherServer struct that serves the magazine as RFC 1436 menus and text files
+pub(crate) struct GopherServer {
+    listeners: Vec<TcpListener>,
+    shelf: Shelf,
+    host: String,
+    port: u16,
+}
+
+impl GopherServer {
+    // Bind the Gopher listeners up front so address errors surface at startup. Menus point
+    // clients back at `host` and `port`, which must be how readers reach this server.
+    pub async fn new(addrs: &[String], shelf: Shelf, host: &str, port: u16) -> Result<Self> {
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            listeners.push(TcpListener::bind(addr).await?);
+        }
+        Ok(GopherServer { listeners, shelf, host: host.to_string(), port })
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            info!("Listening for Gopher on: {}", listener.local_addr()?);
+            accept_loops.spawn(accept_loop(listener, self.shelf.clone(), self.host.clone(), self.port));
+        }
+        // Accept loops only end on a listener error, which takes the whole server down
+        while let Some(result) = accept_loops.join_next().await {
+            result.map_err(std::io::Error::other)??;
+        }
+        Ok(())
+    }
+}
+
+async fn accept_loop(listener: TcpListener, shelf: Shelf, host: String, port: u16) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (shelf, host) = (shelf.clone(), host.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, peer, &shelf, &host, port).await {
+                warn!(%peer, "Error handling Gopher request: {}", e);
+            }
+        });
+    }
+}
+
+// Answer one request: read the selector line, write the menu or text file and close
+async fn serve(mut stream: TcpStream, peer: SocketAddr, shelf: &Shelf, host: &str, port: u16) -> Result<()> {
+    if sessions::is_banned(peer.ip()) {
+        info!(%peer, "Refused banned address");
+        metrics::rejected("gopher", "banned");
+        return Ok(());
+    }
+    let mut line = String::new();
+    let mut reader = BufReader::new(&mut stream).take(MAX_SELECTOR);
+    if timeout(REQUEST_TIMEOUT, reader.read_line(&mut line)).await.is_err() {
+        return Ok(());
+    }
+    // Gopher+ clients append a tab and more fields after the selector
+    let selector = line.trim_end_matches(['\r', '\n']).split('\t').next().unwrap_or("");
+    debug!(%peer, selector, "Gopher request");
+
+    let magazine = shelf.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    let response = Menus { magazine: &magazine, host, port }.respond(selector);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// Define the Menus struct that maps selectors onto the magazine:
+// "/" the issue, "/cover" and "/front" text, "/<section>" a section menu and "/<section>/<page>" a page
+struct Menus<'a> {
+    magazine: &'a Magazine,
+    host: &'a str,
+    port: u16,
+}
+
+impl Menus<'_> {
+    fn respond(&self, selector: &str) -> String {
+        let parts: Vec<&str> = selector.split('/').filter(|part| !part.is_empty()).collect();
+        let numbers: Option<Vec<usize>> = parts.iter().map(|part| part.parse::<usize>().ok().filter(|&n| n > 0)).collect();
+        match (parts.as_slice(), numbers.as_deref()) {
+            ([], _) => self.issue(),
+            (["cover"], _) => text(&self.magazine.cover_text),
+            (["front"], _) => text(&self.magazine.front_text),
+            (_, Some(&[section])) => self.section(section),
+            (_, Some(&[section, page])) => match self.magazine.get_section(section - 1).and_then(|s| s.get_page(page - 1)) {
+                Some(content) => {
+                    metrics::page_view(section, page);
+                    text(&content.text)
+                }
+                None => self.error("No such page"),
+            },
+            _ => self.error("Not found"),
+        }
+    }
+
+    fn issue(&self) -> String {
+        let mut menu = self.info(&self.magazine.title);
+        menu.push_str(&self.info(""));
+        menu.push_str(&self.item('0', "Cover", "/cover"));
+        menu.push_str(&self.item('0', "Front page", "/front"));
+        menu.push_str(&self.info(""));
+        for (index, section) in self.magazine.all_sections().into_iter().enumerate() {
+            menu.push_str(&self.item('1', &format!("{} by {}", section.title, section.author), &format!("/{}", index + 1)));
+        }
+        menu.push_str(".\r\n");
+        menu
+    }
+
+    fn section(&self, section: usize) -> String {
+        let Some(current) = self.magazine.get_section(section - 1) else {
+            return self.error("No such section");
+        };
+        let mut menu = self.info(&format!("{} by {}", current.title, current.author));
+        menu.push_str(&self.info(""));
+        for (page, content) in current.all_pages().into_iter().enumerate() {
+            menu.push_str(&self.item('0', &content.display_title(), &format!("/{}/{}", section, page + 1)));
+        }
+        menu.push_str(&self.info(""));
+        menu.push_str(&self.item('1', &format!("Back to {}", self.magazine.title), "/"));
+        menu.push_str(".\r\n");
+        menu
+    }
+
+    // A menu line: item type, what the reader sees, then where it leads
+    fn item(&self, kind: char, display: &str, selector: &str) -> String {
+        format!("{}{}\t{}\t{}\t{}\r\n", kind, menu_text(display), selector, self.host, self.port)
+    }
+
+    // Informational text in a menu, shown but not selectable
+    fn info(&self, display: &str) -> String {
+        format!("i{}\t\terror.host\t1\r\n", menu_text(display))
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("3{}\t\terror.host\t1\r\n.\r\n", message)
+    }
+}
+
+// Tabs and line breaks would split a menu line into bogus fields
+fn menu_text(display: &str) -> String {
+    ansi::strip(display).replace(['\t', '\r', '\n'], " ")
+}
+
+// A text file with the styling stripped, CRLF line endings and the lone "." that ends it,
+// doubling the dot of any line that starts with one so the client doesn't stop early
+fn text(content: &str) -> String {
+    let mut body = String::new();
+    for line in ansi::strip(content).trim_end().lines() {
+        if line.starts_with('.') {
+            body.push('.');
+        }
+        body.push_str(line);
+        body.push_str("\r\n");
+    }
+    body.push_str(".\r\n");
+    body
+}
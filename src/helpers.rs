@@ -1,6 +1,16 @@
 use handlebars::{Handlebars, Helper, HelperResult, Context, RenderContext, Output, RenderError};
 use crossterm::style::{Color, Stylize};
 
+/// Registers every helper pages can use, so all templates see the same set
+pub fn register_helpers(engine: &mut Handlebars<'_>) {
+    engine.register_helper("color", Box::new(color_helper));
+    engine.register_helper("italic", Box::new(italic_helper));
+    engine.register_helper("bold", Box::new(bold_helper));
+    engine.register_helper("underline", Box::new(underline_helper));
+    engine.register_helper("rainbow", Box::new(rainbow_helper));
+    engine.register_helper("add", Box::new(add_helper));
+}
+
 pub fn add_helper(h: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
     // Get the first argument from the template
     let param1 = h.param(0).ok_or(RenderError::new("Missing parameter"))?.value().as_f64().ok_or(RenderError::new("Invalid parameter type"))?;
@@ -151,6 +161,21 @@ pub fn rainbow_helper(
     Ok(())
 }
 
+// Map a color name as used in templates and the theme config to a terminal color
+pub fn parse_color(name: &str) -> Option<Color> {
+    match name.to_lowercase().as_str() {
+        "black" => Some(Color::Black),
+        "red" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "yellow" => Some(Color::Yellow),
+        "blue" => Some(Color::Blue),
+        "purple" => Some(Color::Magenta),
+        "cyan" => Some(Color::Cyan),
+        "white" => Some(Color::White),
+        _ => None,
+    }
+}
+
 mod lol {
     use crossterm::style::Color;
 
@@ -0,0 +1,454 @@
+// What readers type to get around a magazine. Section and page numbers and a bare ENTER
+// depend on what's on screen; everything else is a command that works from anywhere.
+use serde::{Deserialize, Serialize};
+
+// Define the Action enum for the commands a reader can type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Next,
+    Previous,
+    Index,
+    Front,
+    Tags,
+    Bookmarks,
+    Comments,
+    Comment,
+    Chat,
+    Help,
+    Quit,
+    // Commands typed as a prefix followed by their argument
+    Search,
+    Tag,
+    Bookmark,
+    Resume,
+    Handle,
+}
+
+impl Action {
+    // The action's key in the [keymap] config and the magazine's keymap
+    fn name(self) -> &'static str {
+        match self {
+            Action::Next => "next",
+            Action::Previous => "previous",
+            Action::Index => "index",
+            Action::Front => "front",
+            Action::Tags => "tags",
+            Action::Bookmarks => "bookmarks",
+            Action::Comments => "comments",
+            Action::Comment => "comment",
+            Action::Chat => "chat",
+            Action::Help => "help",
+            Action::Quit => "quit",
+            Action::Search => "search",
+            Action::Tag => "tag",
+            Action::Bookmark => "bookmark",
+            Action::Resume => "resume",
+            Action::Handle => "handle",
+        }
+    }
+}
+
+// The command table: every action with what it does, in the order the help screen lists them
+const COMMANDS: [(Action, &str, &str); 16] = [
+    (Action::Next, "", "Next page while reading"),
+    (Action::Previous, "", "Previous page while reading"),
+    (Action::Index, "", "The table of contents of the section you're reading"),
+    (Action::Front, "", "Back to the front page"),
+    (Action::Search, "words", "Search every page for the words"),
+    (Action::Tags, "", "Topics in every issue"),
+    (Action::Tag, "topic", "Everything tagged with a topic"),
+    (Action::Bookmark, " name", "Bookmark the page you're reading"),
+    (Action::Bookmarks, "", "Your bookmarks and where you left off"),
+    (Action::Resume, " code", "Pick up where you left off, by resume code or handle"),
+    (Action::Handle, " name", "Pick a handle to resume by instead of a code"),
+    (Action::Comments, "", "What readers said about the section you're reading"),
+    (Action::Comment, "", "Leave a comment on the section you're reading"),
+    (Action::Chat, "", "Talk with the other readers connected right now"),
+    (Action::Help, "", "This screen"),
+    (Action::Quit, "", "Leave"),
+];
+
+/// Names of the built-in keymaps, for `preset` in the config and the magazine's keymap
+pub const PRESETS: [&str; 4] = ["default", "less", "vi", "bbs-classic"];
+
+// Define the Keymap struct holding the words (or, for prefix commands, the prefixes) bound to each action
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    pub next: Vec<String>,
+    pub previous: Vec<String>,
+    pub index: Vec<String>,
+    pub front: Vec<String>,
+    pub tags: Vec<String>,
+    pub help: Vec<String>,
+    pub quit: Vec<String>,
+    pub search: Vec<String>,
+    pub tag: Vec<String>,
+    pub bookmark: Vec<String>,
+    pub bookmarks: Vec<String>,
+    pub resume: Vec<String>,
+    pub handle: Vec<String>,
+    pub comments: Vec<String>,
+    pub comment: Vec<String>,
+    pub chat: Vec<String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset("default").unwrap_or_else(|| panic!("The default keymap is missing"))
+    }
+}
+
+impl Keymap {
+    /// One of the built-in keymaps by name, see `PRESETS`
+    pub fn preset(name: &str) -> Option<Self> {
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        let keymap = match name {
+            "default" => Keymap {
+                next: words(&[]),
+                previous: words(&["p", "prev"]),
+                index: words(&["i", "index"]),
+                front: words(&["f", "front"]),
+                tags: words(&["tags"]),
+                help: words(&["?", "help"]),
+                quit: words(&["x", "X", "exit", "quit"]),
+                search: words(&["/", "search "]),
+                tag: words(&["#"]),
+                bookmark: words(&["mark"]),
+                bookmarks: words(&["bookmarks"]),
+                resume: words(&["resume"]),
+                handle: words(&["handle"]),
+                comments: words(&["comments"]),
+                comment: words(&["comment"]),
+                chat: words(&["chat"]),
+            },
+            // Paging like less(1): f and b to turn pages, q to leave
+            "less" => Keymap {
+                next: words(&["f", "j"]),
+                previous: words(&["b", "k"]),
+                index: words(&["i"]),
+                front: words(&["g"]),
+                tags: words(&["t"]),
+                help: words(&["h", "H"]),
+                quit: words(&["q", "Q", ":q"]),
+                search: words(&["/"]),
+                tag: words(&["#"]),
+                bookmark: words(&["m"]),
+                bookmarks: words(&["'"]),
+                resume: words(&["resume"]),
+                handle: words(&["handle"]),
+                comments: words(&["c"]),
+                comment: words(&["C"]),
+                chat: words(&["chat"]),
+            },
+            // Paging like vi: j and k, ex commands for the rest
+            "vi" => Keymap {
+                next: words(&["j", "l"]),
+                previous: words(&["k", "h"]),
+                index: words(&["i"]),
+                front: words(&["gg"]),
+                tags: words(&[":tags"]),
+                help: words(&[":help", ":h"]),
+                quit: words(&[":q", ":q!", ":wq", "ZZ"]),
+                search: words(&["/"]),
+                tag: words(&[":tag ", "#"]),
+                bookmark: words(&["m"]),
+                bookmarks: words(&[":marks"]),
+                resume: words(&[":resume"]),
+                handle: words(&[":handle"]),
+                comments: words(&[":comments"]),
+                comment: words(&[":comment"]),
+                chat: words(&[":chat"]),
+            },
+            // Single letters in either case, like the menus of an old bulletin board
+            "bbs-classic" => Keymap {
+                next: words(&["n", "N"]),
+                previous: words(&["p", "P"]),
+                index: words(&["i", "I"]),
+                front: words(&["m", "M"]),
+                tags: words(&["t", "T"]),
+                help: words(&["?", "h", "H"]),
+                quit: words(&["g", "G", "q", "Q"]),
+                search: words(&["s ", "S "]),
+                tag: words(&["#"]),
+                bookmark: words(&["b", "B"]),
+                bookmarks: words(&["l", "L"]),
+                resume: words(&["r", "R"]),
+                handle: words(&["u", "U"]),
+                comments: words(&["c", "C"]),
+                comment: words(&["w", "W"]),
+                chat: words(&["j", "J"]),
+            },
+            _ => return None,
+        };
+        Some(keymap)
+    }
+
+    fn keys(&self, action: Action) -> &[String] {
+        match action {
+            Action::Next => &self.next,
+            Action::Previous => &self.previous,
+            Action::Index => &self.index,
+            Action::Front => &self.front,
+            Action::Tags => &self.tags,
+            Action::Help => &self.help,
+            Action::Quit => &self.quit,
+            Action::Search => &self.search,
+            Action::Tag => &self.tag,
+            Action::Bookmark => &self.bookmark,
+            Action::Bookmarks => &self.bookmarks,
+            Action::Resume => &self.resume,
+            Action::Handle => &self.handle,
+            Action::Comments => &self.comments,
+            Action::Comment => &self.comment,
+            Action::Chat => &self.chat,
+        }
+    }
+
+    fn keys_mut(&mut self, action: Action) -> &mut Vec<String> {
+        match action {
+            Action::Next => &mut self.next,
+            Action::Previous => &mut self.previous,
+            Action::Index => &mut self.index,
+            Action::Front => &mut self.front,
+            Action::Tags => &mut self.tags,
+            Action::Help => &mut self.help,
+            Action::Quit => &mut self.quit,
+            Action::Search => &mut self.search,
+            Action::Tag => &mut self.tag,
+            Action::Bookmark => &mut self.bookmark,
+            Action::Bookmarks => &mut self.bookmarks,
+            Action::Resume => &mut self.resume,
+            Action::Handle => &mut self.handle,
+            Action::Comments => &mut self.comments,
+            Action::Comment => &mut self.comment,
+            Action::Chat => &mut self.chat,
+        }
+    }
+
+    /// The first key bound to an action, for pointing readers at it
+    pub fn key(&self, action: Action) -> Option<&str> {
+        self.keys(action).first().map(|key| key.trim())
+    }
+
+    /// The command the reader typed, if any, with the argument of a prefix command
+    pub fn command<'a>(&self, input: &'a str) -> Option<(Action, &'a str)> {
+        // Quit words are matched exactly as typed, so a stray space never disconnects anyone
+        if self.quit.iter().any(|word| word == input) {
+            return Some((Action::Quit, ""));
+        }
+        let input = input.trim();
+        for (action, argument, _) in COMMANDS {
+            let keys = self.keys(action);
+            if argument.is_empty() {
+                if keys.iter().any(|key| key.trim() == input) {
+                    return Some((action, ""));
+                }
+            } else if let Some(rest) = keys.iter().find_map(|key| after_prefix(key, input)) {
+                return Some((action, rest.trim()));
+            }
+        }
+        None
+    }
+
+    /// Bindings that hide each other or the numbers and ENTER readers type to get around,
+    /// worth a warning but not worth refusing to start over
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        for (i, (action, argument, _)) in COMMANDS.iter().enumerate() {
+            for key in self.keys(*action) {
+                let word = key.trim();
+                if argument.is_empty() && word.is_empty() {
+                    conflicts.push(format!("{}: an empty key takes the place of ENTER", action.name()));
+                    continue;
+                }
+                if word.parse::<usize>().is_ok() {
+                    conflicts.push(format!("{}: {:?} hides the section and page numbers", action.name(), key));
+                }
+                for (other, other_argument, _) in &COMMANDS[i + 1..] {
+                    for other_key in self.keys(*other) {
+                        let same = word == other_key.trim();
+                        // A word that a prefix takes reads as that prefix command plus an argument,
+                        // and a prefix that takes another prefix swallows that command
+                        let shadowed = match (argument.is_empty(), other_argument.is_empty()) {
+                            (true, false) => after_prefix(other_key, word).is_some(),
+                            (false, true) => after_prefix(key, other_key.trim()).is_some(),
+                            (false, false) => {
+                                after_prefix(key, other_key.trim_start()).is_some() || after_prefix(other_key, key.trim_start()).is_some()
+                            }
+                            (true, true) => false,
+                        };
+                        if same || shadowed {
+                            conflicts.push(format!("{:?} for {} clashes with {:?} for {}", key, action.name(), other_key, other.name()));
+                        }
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// The generated list of commands for the help screen: what to type, then what it does
+    pub fn commands(&self, sections: usize) -> String {
+        let mut rows = vec![
+            (format!("1-{}", sections.max(1)), "Open a section from the front page".to_string()),
+            ("ENTER".to_string(), "Next page while reading".to_string()),
+            ("0".to_string(), "The cover, from the front page".to_string()),
+        ];
+        for (action, argument, description) in COMMANDS {
+            let keys = self.keys(action);
+            if keys.is_empty() {
+                continue;
+            }
+            let typed: Vec<String> = keys
+                .iter()
+                .map(|key| if argument.is_empty() { key.trim().to_string() } else { format!("{}{}", key.trim_start(), argument) })
+                .collect();
+            rows.push((typed.join(", "), description.to_string()));
+        }
+        let width = rows.iter().map(|(typed, _)| typed.chars().count()).max().unwrap_or(0);
+        rows.into_iter().map(|(typed, description)| format!("  {:<width$}  {}\r\n", typed, description, width = width)).collect()
+    }
+}
+
+// What follows a prefix key in the input, if the input is that command. A key ending in a
+// letter or digit has to be followed by a space or nothing, so "m" doesn't take "more"
+fn after_prefix<'a>(key: &str, input: &'a str) -> Option<&'a str> {
+    let prefix = key.trim_start();
+    if prefix.trim().is_empty() {
+        return None;
+    }
+    let rest = input.strip_prefix(prefix)?;
+    let ends_in_word = prefix.ends_with(char::is_alphanumeric);
+    (!ends_in_word || rest.is_empty() || rest.starts_with(char::is_whitespace)).then_some(rest)
+}
+
+/// A keymap as written in the [keymap] config or the magazine's index.json: a preset to start
+/// from, with the keys of any action given replacing the preset's
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    /// Built-in keymap to start from: default, less, vi or bbs-classic
+    pub preset: Option<String>,
+    pub next: Option<Vec<String>>,
+    pub previous: Option<Vec<String>>,
+    pub index: Option<Vec<String>>,
+    pub front: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    pub help: Option<Vec<String>>,
+    /// Words that disconnect the reader, matched exactly as typed
+    pub quit: Option<Vec<String>>,
+    /// Prefixes typed before the words to search for
+    pub search: Option<Vec<String>>,
+    /// Prefixes typed before a tag
+    pub tag: Option<Vec<String>>,
+    /// Prefixes typed before a bookmark's name, which can be left out
+    pub bookmark: Option<Vec<String>>,
+    pub bookmarks: Option<Vec<String>>,
+    /// Prefixes typed before a resume code or handle
+    pub resume: Option<Vec<String>>,
+    /// Prefixes typed before the handle a reader picks
+    pub handle: Option<Vec<String>>,
+    pub comments: Option<Vec<String>>,
+    /// Words that start writing a comment on the section
+    pub comment: Option<Vec<String>>,
+    /// Words that open the chat room
+    pub chat: Option<Vec<String>>,
+}
+
+impl KeymapConfig {
+    /// This keymap on top of `base`, which is used as is when no preset is named
+    pub fn apply(&self, base: &Keymap) -> Result<Keymap, String> {
+        let mut keymap = match &self.preset {
+            Some(name) => Keymap::preset(name)
+                .ok_or_else(|| format!("unknown keymap preset {:?}, expected one of {}", name, PRESETS.join(", ")))?,
+            None => base.clone(),
+        };
+        let bindings = [
+            (Action::Next, &self.next),
+            (Action::Previous, &self.previous),
+            (Action::Index, &self.index),
+            (Action::Front, &self.front),
+            (Action::Tags, &self.tags),
+            (Action::Help, &self.help),
+            (Action::Quit, &self.quit),
+            (Action::Search, &self.search),
+            (Action::Tag, &self.tag),
+            (Action::Bookmark, &self.bookmark),
+            (Action::Bookmarks, &self.bookmarks),
+            (Action::Resume, &self.resume),
+            (Action::Handle, &self.handle),
+            (Action::Comments, &self.comments),
+            (Action::Comment, &self.comment),
+            (Action::Chat, &self.chat),
+        ];
+        for (action, keys) in bindings {
+            if let Some(keys) = keys {
+                *keymap.keys_mut(action) = keys.clone();
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(preset: &str) -> Keymap {
+        Keymap::preset(preset).unwrap()
+    }
+
+    #[test]
+    fn commands() {
+        let default = keymap("default");
+        assert_eq!(default.command(" p "), Some((Action::Previous, "")));
+        assert_eq!(default.command("/retro  bbs "), Some((Action::Search, "retro  bbs")));
+        assert_eq!(default.command("#Fiction"), Some((Action::Tag, "Fiction")));
+        assert_eq!(default.command("mark"), Some((Action::Bookmark, "")));
+        assert_eq!(default.command("mark later"), Some((Action::Bookmark, "later")));
+        assert_eq!(default.command("market"), None);
+        assert_eq!(default.command("searching"), None);
+        assert_eq!(default.command("exit"), Some((Action::Quit, "")));
+        assert_eq!(default.command("3"), None);
+
+        let less = keymap("less");
+        assert_eq!(less.command("m"), Some((Action::Bookmark, "")));
+        assert_eq!(less.command("m work"), Some((Action::Bookmark, "work")));
+        assert_eq!(less.command("more"), None);
+
+        let bbs = keymap("bbs-classic");
+        assert_eq!(bbs.command("B"), Some((Action::Bookmark, "")));
+        assert_eq!(bbs.command("r ABCD-1234"), Some((Action::Resume, "ABCD-1234")));
+        assert_eq!(bbs.command("u zero"), Some((Action::Handle, "zero")));
+        assert_eq!(bbs.command("bye"), None);
+        assert_eq!(bbs.command("read"), None);
+        assert_eq!(bbs.command("usage"), None);
+        assert_eq!(bbs.command("s modem"), Some((Action::Search, "modem")));
+
+        let vi = keymap("vi");
+        assert_eq!(vi.command(":tag fiction"), Some((Action::Tag, "fiction")));
+        assert_eq!(vi.command(":tags"), Some((Action::Tags, "")));
+    }
+
+    #[test]
+    fn presets_have_no_conflicts() {
+        for preset in PRESETS {
+            assert_eq!(keymap(preset).conflicts(), Vec::<String>::new(), "{}", preset);
+        }
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut keymap = keymap("default");
+        keymap.help = vec!["#help".to_string()];
+        keymap.resume = vec!["/r".to_string()];
+        keymap.chat = vec!["".to_string()];
+        keymap.comments = vec!["2".to_string()];
+        let conflicts = keymap.conflicts();
+        assert!(conflicts.contains(&"\"/\" for search clashes with \"/r\" for resume".to_string()), "{:?}", conflicts);
+        assert!(conflicts.contains(&"\"#\" for tag clashes with \"#help\" for help".to_string()), "{:?}", conflicts);
+        assert!(conflicts.contains(&"chat: an empty key takes the place of ENTER".to_string()), "{:?}", conflicts);
+        assert!(conflicts.contains(&"comments: \"2\" hides the section and page numbers".to_string()), "{:?}", conflicts);
+        assert_eq!(conflicts.len(), 4, "{:?}", conflicts);
+    }
+}
@@ -0,0 +1,101 @@
+// Frontends whose clients send raw keystrokes (ssh ptys, browser terminals) need to do
+// the echo and line assembly that a telnet client in line mode does for us.
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum KeyEvent {
+    // Bytes to echo back so the reader sees what they type
+    Echo(String),
+    // A full line was entered
+    Line(String),
+    // Ctrl-C or Ctrl-D, treated like "exit"
+    Interrupt,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LineEditor {
+    line: String,
+    // Echo stars instead of what's typed, for passwords
+    pub hidden: bool,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor::default()
+    }
+
+    // Feed raw keystrokes in, get echo, completed lines and interrupts out in order
+    pub fn feed(&mut self, keys: &str) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        let mut echo = String::new();
+        for c in keys.chars() {
+            match c {
+                '\r' | '\n' => {
+                    echo.push_str("\r\n");
+                    events.push(KeyEvent::Echo(std::mem::take(&mut echo)));
+                    events.push(KeyEvent::Line(std::mem::take(&mut self.line)));
+                }
+                '\x7f' | '\x08' => {
+                    if self.line.pop().is_some() {
+                        echo.push_str("\x08 \x08");
+                    }
+                }
+                '\x03' | '\x04' => {
+                    if !echo.is_empty() {
+                        events.push(KeyEvent::Echo(std::mem::take(&mut echo)));
+                    }
+                    events.push(KeyEvent::Interrupt);
+                    return events;
+                }
+                c if c.is_control() => {}
+                c => {
+                    self.line.push(c);
+                    echo.push(if self.hidden { '*' } else { c });
+                }
+            }
+        }
+        if !echo.is_empty() {
+            events.push(KeyEvent::Echo(echo));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(text: &str) -> KeyEvent {
+        KeyEvent::Echo(text.to_string())
+    }
+
+    fn line(text: &str) -> KeyEvent {
+        KeyEvent::Line(text.to_string())
+    }
+
+    #[test]
+    fn lines_and_echo() {
+        let mut editor = LineEditor::new();
+        assert_eq!(editor.feed("ab"), [echo("ab")]);
+        assert_eq!(editor.feed("c\x7f\x7fd\r"), [echo("c\x08 \x08\x08 \x08d\r\n"), line("ad")]);
+        // Backspace on an empty line and stray control characters echo nothing
+        assert_eq!(editor.feed("\x7f\x1bx"), [echo("x")]);
+        assert_eq!(editor.feed("\ry\r"), [echo("\r\n"), line("x"), echo("y\r\n"), line("y")]);
+        assert_eq!(editor.feed(""), []);
+    }
+
+    #[test]
+    fn hidden_input_echoes_stars() {
+        let mut editor = LineEditor::new();
+        editor.hidden = true;
+        assert_eq!(editor.feed("pässword\x7f!\r"), [echo("********\x08 \x08*\r\n"), line("pässwor!")]);
+        editor.hidden = false;
+        assert_eq!(editor.feed("ok"), [echo("ok")]);
+    }
+
+    #[test]
+    fn interrupts_stop_the_line() {
+        let mut editor = LineEditor::new();
+        assert_eq!(editor.feed("ab\x03cd"), [echo("ab"), KeyEvent::Interrupt]);
+        assert_eq!(editor.feed("\x04"), [KeyEvent::Interrupt]);
+    }
+}
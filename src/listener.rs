@@ -0,0 +1,238 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+// The first file descriptor systemd passes to socket-activated services
+const LISTEN_FDS_START: i32 = 3;
+
+// Define the Listener enum covering every source of reader connections
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    // Bind a TCP listener on "ip:port", IPv4 or IPv6
+    pub async fn bind_tcp(ip: &str, port: u16) -> Result<Self> {
+        Ok(Listener::Tcp(TcpListener::bind(host_port(ip, port)).await?))
+    }
+
+    // Bind a Unix domain socket, replacing a stale socket file left by a previous run
+    pub fn bind_unix(path: &Path) -> Result<Self> {
+        remove_stale_socket(path)?;
+        Ok(Listener::Unix(UnixListener::bind(path)?, path.to_path_buf()))
+    }
+
+    // Take over the `count` sockets systemd passed in, see take_systemd_fds
+    pub fn from_systemd(count: i32) -> Result<Vec<Self>> {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+        (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Safety: systemd hands these descriptors to us and nothing else in the process owns them
+                let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+                // getsockname only yields an inet address for TCP sockets, anything else is treated as Unix
+                if tcp.local_addr().is_ok() {
+                    tcp.set_nonblocking(true)?;
+                    return Ok(Listener::Tcp(TcpListener::from_std(tcp)?));
+                }
+                let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+                unix.set_nonblocking(true)?;
+                let path = unix
+                    .local_addr()?
+                    .as_pathname()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| PathBuf::from(format!("fd:{}", fd)));
+                Ok(Listener::Unix(UnixListener::from_std(unix)?, path))
+            })
+            .collect()
+    }
+
+    pub async fn accept(&self) -> Result<(Connection, PeerAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Connection::Tcp(stream), PeerAddr::Tcp(addr)))
+            }
+            Listener::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Connection::Unix(stream), PeerAddr::Unix(path.clone())))
+            }
+        }
+    }
+
+    // Describe where the listener is bound, for the startup log
+    pub fn local_addr(&self) -> Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            Listener::Unix(_, path) => Ok(format!("unix:{}", path.display())),
+        }
+    }
+}
+
+/// How many sockets systemd passed in through LISTEN_PID/LISTEN_FDS, clearing those variables so
+/// they aren't passed on to anything we might spawn. Call it before starting any threads: changing
+/// the environment while another thread reads it is a data race
+pub(crate) fn take_systemd_fds() -> Result<i32> {
+    let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(std::process::id()) {
+        return Err(Error::new(ErrorKind::NotFound, "LISTEN_PID is not set for this process, was telzine socket-activated?"));
+    }
+    let count = std::env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse().ok());
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    count.ok_or_else(|| Error::new(ErrorKind::NotFound, "LISTEN_FDS is not set"))
+}
+
+/// Clears the way to bind a Unix socket at `path`: nothing there is fine, and a socket nobody
+/// answers on is left over from an earlier run and removed. Anything else, a live socket or
+/// a file that isn't a socket at all, is an error rather than something to delete
+pub(crate) fn remove_stale_socket(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::new(ErrorKind::AlreadyExists, format!("{:?} exists and is not a socket, refusing to replace it", path)));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(Error::new(ErrorKind::AddrInUse, format!("{:?} is in use by another process", path)));
+    }
+    std::fs::remove_file(path)
+}
+
+// Format an address for binding, bracketing IPv6 literals
+pub(crate) fn host_port(ip: &str, port: u16) -> String {
+    if ip.contains(':') && !ip.starts_with('[') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    }
+}
+
+// Where a reader connected from: an IP address, or the Unix socket they came in on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PeerAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl PeerAddr {
+    // The reader's IP address, None for Unix socket connections
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            PeerAddr::Tcp(addr) => Some(addr.ip()),
+            PeerAddr::Unix(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddr::Tcp(addr)
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// An accepted connection from any Listener
+pub(crate) enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Connection::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Connection::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Connection::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Connection::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A path of its own for each test under the system's temporary directory
+    fn scratch(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("telzine-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn stale_sockets_are_replaced() {
+        let path = scratch("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+        // Nothing there is fine too
+        remove_stale_socket(&path).unwrap();
+    }
+
+    #[test]
+    fn live_sockets_are_left_alone() {
+        let path = scratch("live.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert_eq!(remove_stale_socket(&path).unwrap_err().kind(), ErrorKind::AddrInUse);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_never_removed() {
+        let path = scratch("not-a-socket");
+        std::fs::write(&path, "precious").unwrap();
+        assert_eq!(remove_stale_socket(&path).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "precious");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ipv6_literals_are_bracketed() {
+        assert_eq!(host_port("::1", 23), "[::1]:23");
+        assert_eq!(host_port("[::1]", 23), "[::1]:23");
+        assert_eq!(host_port("127.0.0.1", 23), "127.0.0.1:23");
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use chrono::prelude::*;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{info, info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::chat::ChatMembership;
+use crate::config::LoggingConfig;
+use crate::listener::PeerAddr;
+use crate::metrics;
+use crate::recording::Recording;
+use crate::sessions::{self, Control};
+use crate::telnet::TelnetHandler;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+static ACCESS_LOG: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Installs the leveled server log and opens the access log, as configured
+pub fn init(config: &LoggingConfig) -> Result<(), String> {
+    let filter = EnvFilter::try_new(config.level.to_lowercase()).map_err(|e| format!("logging.level: {}", e))?;
+    let json = config.format.eq_ignore_ascii_case("json");
+
+    match &config.file {
+        Some(path) => {
+            let file = Mutex::new(open_append(path)?);
+            let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(file).with_ansi(false);
+            if json {
+                builder.json().init();
+            } else {
+                builder.init();
+            }
+        }
+        None => {
+            let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+            if json {
+                builder.json().init();
+            } else {
+                builder.init();
+            }
+        }
+    }
+
+    if let Some(path) = &config.access_log {
+        let writer: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(open_append(path)?)
+        };
+        let _ = ACCESS_LOG.set(Mutex::new(writer));
+    }
+    Ok(())
+}
+
+fn open_append(path: &PathBuf) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open log file {:?}: {}", path, e))
+}
+
+// Write one JSON line to the access log, if there is one
+fn access(mut record: Value) {
+    let Some(log) = ACCESS_LOG.get() else {
+        return;
+    };
+    record["ts"] = json!(Local::now().to_rfc3339());
+    let mut log = log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _ = writeln!(log, "{}", record);
+    let _ = log.flush();
+}
+
+/// Tracks one reader's visit: gives it an ID for the server log and records connect,
+/// page views and disconnect (with duration) in the access log
+pub(crate) struct SessionLog {
+    id: u64,
+    frontend: &'static str,
+    peer: String,
+    span: Span,
+    started: Instant,
+    pages_viewed: usize,
+    last_page: Option<(usize, usize)>,
+    reason: &'static str,
+    terminal: Option<String>,
+    window_size: bool,
+    recording: Option<Recording>,
+    control: Option<UnboundedReceiver<Control>>,
+    // The reader is typing a password, which stays out of the recording
+    hidden: bool,
+    // Takes the session out of the chat room when the connection closes
+    _chat: ChatMembership,
+}
+
+impl SessionLog {
+    pub fn start(frontend: &'static str, peer: impl Into<PeerAddr>) -> Self {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let peer: PeerAddr = peer.into();
+        let control = sessions::register(id, frontend, peer.clone());
+        let peer = peer.to_string();
+        let span = info_span!("session", id, frontend);
+        span.in_scope(|| info!(%peer, "Got connection"));
+        access(json!({ "event": "connect", "session": id, "frontend": frontend, "peer": peer }));
+        metrics::connected(frontend);
+        let recording = Recording::start(id, frontend);
+        if let Some(recording) = &recording {
+            span.in_scope(|| info!("Recording to {:?}", recording.path()));
+        }
+        SessionLog {
+            id,
+            frontend,
+            peer,
+            span,
+            started: Instant::now(),
+            pages_viewed: 0,
+            last_page: None,
+            reason: "closed",
+            terminal: None,
+            window_size: false,
+            recording,
+            control: Some(control),
+            hidden: false,
+            _chat: ChatMembership::new(id),
+        }
+    }
+
+    // The ID the admin console knows the session by
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // The span every log line of this session should be recorded in
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    // Call after every input so newly opened pages are recorded
+    pub fn observe<H: TelnetHandler>(&mut self, handler: &H) {
+        let page = handler.current_page();
+        if let Some((section, page_index)) = page.filter(|_| page != self.last_page) {
+            self.pages_viewed += 1;
+            self.span.in_scope(|| tracing::debug!(section = section + 1, page = page_index + 1, "Viewing page"));
+            access(json!({
+                "event": "page_view",
+                "session": self.id,
+                "section": section + 1,
+                "page": page_index + 1,
+            }));
+            metrics::page_view(section + 1, page_index + 1);
+        }
+        let user = handler.identity();
+        sessions::update(self.id, |info| {
+            info.page = page.map(|(section, page_index)| (section + 1, page_index + 1));
+            info.user = user;
+        });
+        self.hidden = handler.hide_input();
+        self.last_page = page;
+    }
+
+    // Why the session ended, "closed" (by the client) unless set
+    pub fn set_reason(&mut self, reason: &'static str) {
+        self.reason = reason;
+    }
+
+    // The terminal type the client announced (Telnet TTYPE, SSH pty-req)
+    pub fn set_terminal(&mut self, terminal: &str) {
+        if let Some(recording) = &mut self.recording {
+            recording.set_terminal(terminal);
+        }
+        // Keep the metric label set small and printable whatever the client sends
+        let terminal: String = terminal
+            .to_lowercase()
+            .chars()
+            .take(32)
+            .map(|c| if c.is_ascii_alphanumeric() || "+-._".contains(c) { c } else { '_' })
+            .collect();
+        self.span.in_scope(|| tracing::debug!(%terminal, "Terminal type"));
+        sessions::update(self.id, |info| info.terminal = Some(terminal.clone()));
+        self.terminal = Some(terminal);
+    }
+
+    // Wait for the next instruction from the admin console
+    pub async fn control(&mut self) -> Control {
+        if let Some(control) = &mut self.control {
+            if let Some(control) = control.recv().await {
+                return control;
+            }
+        }
+        // Taken by the frontend, nothing will ever arrive here
+        std::future::pending().await
+    }
+
+    // Hand the admin instructions to a task of their own, for frontends that can't wait on them inline
+    pub fn take_control(&mut self) -> Option<UnboundedReceiver<Control>> {
+        self.control.take()
+    }
+
+    // The client told us its window size
+    pub fn resized(&mut self, width: u16, height: u16) {
+        // Clients without a real terminal report 0x0
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.window_size = true;
+        sessions::update(self.id, |info| info.size = Some((width, height)));
+        if let Some(recording) = &mut self.recording {
+            recording.resize(width, height);
+        }
+    }
+
+    // Output written to the reader, counted for the metrics and recorded
+    pub fn sent(&mut self, output: &str) {
+        metrics::sent(self.frontend, output.len());
+        if let Some(recording) = &mut self.recording {
+            recording.output(output);
+        }
+    }
+
+    // Protocol bytes written to the reader, counted but not part of the recording
+    pub fn sent_control(&self, bytes: usize) {
+        metrics::sent(self.frontend, bytes);
+    }
+
+    // Input from the reader, recorded when input recording is on
+    pub fn received(&mut self, input: &str) {
+        sessions::update(self.id, |info| info.last_input = Instant::now());
+        if let Some(recording) = self.recording.as_mut().filter(|_| !self.hidden) {
+            recording.input(input);
+        }
+    }
+}
+
+impl Drop for SessionLog {
+    fn drop(&mut self) {
+        if sessions::remove(self.id).is_some_and(|info| info.kicked) {
+            self.reason = "kicked";
+        }
+        let elapsed = self.started.elapsed();
+        let duration = elapsed.as_secs_f64();
+        metrics::disconnected(self.frontend, elapsed, self.terminal.as_deref().unwrap_or("unknown"), self.window_size);
+        self.span.in_scope(|| info!(peer = %self.peer, reason = self.reason, duration_secs = duration, pages_viewed = self.pages_viewed, "Disconnection"));
+        access(json!({
+            "event": "disconnect",
+            "session": self.id,
+            "frontend": self.frontend,
+            "peer": self.peer,
+            "reason": self.reason,
+            "duration_secs": (duration * 1000.0).round() / 1000.0,
+            "pages_viewed": self.pages_viewed,
+        }));
+    }
+}
@@ -1,44 +1,375 @@
 mod telnet;
+mod ssh;
+mod web;
+mod line_editor;
+mod listener;
+mod proxy_protocol;
+mod config;
+mod logging;
+mod metrics;
+mod recording;
+mod sessions;
+mod preview;
+mod ansi;
+mod export;
+mod admin;
+mod gopher;
+mod gemini;
+mod finger;
 mod zine;
 mod zine_handler;
+mod search;
+mod keymap;
+mod bookmarks;
+mod accounts;
+mod comments;
+mod chat;
 mod helpers;
 
-use std::{io::Result, path::Path};
+use std::{future::Future, io::Result, path::PathBuf, time::Duration};
+use crossterm::style::Color;
 use structopt::StructOpt;
 
 use crate::{
+    config::Config,
+    export::ExportFormat,
+    helpers::parse_color,
+    listener::{host_port, Listener},
+    proxy_protocol::ProxyProtocol,
+    telnet::SessionLimits,
     zine::Magazine,
     zine_handler::{ZineHandler},
 };
 
+// Command line options override the config file and environment, so none of them have defaults here
 #[derive(StructOpt)]
 #[structopt(name = "telzine")]
 struct Opt {
-    #[structopt(short = "p", long = "port", default_value = "8080")]
-    port: String,
+    #[structopt(short = "c", long = "config", help = "TOML configuration file")]
+    config: Option<PathBuf>,
+
+    #[structopt(short = "p", long = "port", help = "[default: 8080]")]
+    port: Option<u16>,
     
-    #[structopt(short = "i", long, default_value = "127.0.0.1")]
-    ip: String,
+    #[structopt(short = "i", long, number_of_values = 1, help = "Repeat to listen on several addresses, e.g. -i 0.0.0.0 -i :: [default: 127.0.0.1]")]
+    ip: Vec<String>,
+
+    #[structopt(long = "unix-socket", number_of_values = 1, help = "Also serve Telnet on this Unix domain socket (repeatable)")]
+    unix_sockets: Vec<PathBuf>,
+
+    #[structopt(long = "listen-fds", help = "Serve Telnet on the sockets passed by systemd socket activation instead of --ip/--port")]
+    listen_fds: bool,
+
+    #[structopt(short = "m", long = "magazine", help = "[default: ./examples/example-zine]")]
+    magazine_path: Option<PathBuf>,
+
+    #[structopt(long = "tls-port", help = "Also serve over TLS (telnets) on this port")]
+    tls_port: Option<u16>,
+
+    #[structopt(long = "tls-cert", help = "[default: ./cert.pem]")]
+    tls_cert: Option<PathBuf>,
+
+    #[structopt(long = "tls-key", help = "[default: ./key.pem]")]
+    tls_key: Option<PathBuf>,
+
+    #[structopt(long = "ssh-port", help = "Also serve over SSH on this port")]
+    ssh_port: Option<u16>,
+
+    #[structopt(long = "ssh-host-key", help = "Generated on first start if missing [default: ./ssh_host_ed25519_key]")]
+    ssh_host_key: Option<PathBuf>,
+
+    #[structopt(long = "http-port", help = "Also serve a browser terminal over HTTP/WebSocket on this port")]
+    http_port: Option<u16>,
+
+    #[structopt(long = "gopher-port", help = "Also serve the magazine over Gopher on this port")]
+    gopher_port: Option<u16>,
+
+    #[structopt(long = "finger-port", help = "Answer finger queries on this port with the magazine's plan")]
+    finger_port: Option<u16>,
+
+    #[structopt(long = "gemini-port", help = "Also serve the magazine over Gemini on this port, with a self-signed certificate generated on first start")]
+    gemini_port: Option<u16>,
+
+    #[structopt(long = "metrics-port", help = "Serve Prometheus metrics on http://127.0.0.1:<port>/metrics")]
+    metrics_port: Option<u16>,
+
+    #[structopt(long = "record", help = "Record every session to this directory as asciicast v2 files")]
+    record: Option<PathBuf>,
+
+    #[structopt(long = "record-input", help = "Also record what readers type")]
+    record_input: bool,
+
+    #[structopt(long = "admin-socket", help = "Serve the admin console on this Unix socket")]
+    admin_socket: Option<PathBuf>,
+
+    #[structopt(long = "keymap", help = "Keys readers type: default, less, vi or bbs-classic [default: default]")]
+    keymap: Option<String>,
+
+    #[structopt(long = "proxy-protocol", help = "Expect PROXY protocol v1/v2 headers on Telnet, TLS and SSH connections: off, optional or required [default: off]")]
+    proxy_protocol: Option<ProxyProtocol>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Inspect the configuration
+    Config(ConfigCommand),
+    /// Read the magazine in this terminal without starting a server, reloading it whenever a file is saved
+    Preview,
+    /// Write the magazine out as a static site or a single file for archives
+    Export {
+        /// Output format: html, text or ans
+        #[structopt(long, default_value = "html")]
+        format: ExportFormat,
+        /// Directory to write to, created if it doesn't exist
+        #[structopt(short, long, default_value = "export")]
+        out: PathBuf,
+    },
+    /// Play a session recording back in this terminal
+    Replay {
+        /// The .cast file to play
+        file: PathBuf,
+        /// Playback speed multiplier
+        #[structopt(long, default_value = "1.0")]
+        speed: f64,
+        /// Shorten pauses longer than this many seconds
+        #[structopt(long = "max-wait")]
+        max_wait: Option<f64>,
+    },
+}
+
+#[derive(StructOpt)]
+enum ConfigCommand {
+    /// Print the effective configuration after the config file, environment and command line are applied
+    Dump,
+}
 
-    #[structopt(short = "m", long = "magazine", default_value = "./examples/example-zine")]
-    magazine_path: String,
+impl Opt {
+    // Apply the command line on top of the loaded configuration
+    fn apply(&self, config: &mut Config) {
+        let listen = &mut config.listen;
+        if let Some(port) = self.port {
+            listen.port = port;
+        }
+        if !self.ip.is_empty() {
+            listen.ip = self.ip.clone();
+        }
+        listen.unix_sockets.extend(self.unix_sockets.iter().cloned());
+        listen.listen_fds |= self.listen_fds;
+        if let Some(magazine) = &self.magazine_path {
+            config.magazine = magazine.clone();
+        }
+        if self.tls_port.is_some() {
+            listen.tls_port = self.tls_port;
+        }
+        if let Some(tls_cert) = &self.tls_cert {
+            listen.tls_cert = tls_cert.clone();
+        }
+        if let Some(tls_key) = &self.tls_key {
+            listen.tls_key = tls_key.clone();
+        }
+        if self.ssh_port.is_some() {
+            listen.ssh_port = self.ssh_port;
+        }
+        if let Some(ssh_host_key) = &self.ssh_host_key {
+            listen.ssh_host_key = ssh_host_key.clone();
+        }
+        if self.http_port.is_some() {
+            listen.http_port = self.http_port;
+        }
+        if self.gopher_port.is_some() {
+            listen.gopher_port = self.gopher_port;
+        }
+        if self.finger_port.is_some() {
+            listen.finger_port = self.finger_port;
+        }
+        if self.gemini_port.is_some() {
+            listen.gemini_port = self.gemini_port;
+        }
+        if self.metrics_port.is_some() {
+            listen.metrics_port = self.metrics_port;
+        }
+        if self.record.is_some() {
+            config.recording.directory = self.record.clone();
+        }
+        config.recording.input |= self.record_input;
+        if self.admin_socket.is_some() {
+            config.admin.socket = self.admin_socket.clone();
+        }
+        if self.keymap.is_some() {
+            config.keymap.preset = self.keymap.clone();
+        }
+        if let Some(proxy_protocol) = self.proxy_protocol {
+            listen.proxy_protocol = proxy_protocol;
+        }
+    }
+}
 
-    #[structopt(short = "h", long = "help")]
-    help: bool,
+fn main() -> Result<()> {
+    // Taken while this is the only thread, before the runtime starts its workers
+    let systemd_fds = listener::take_systemd_fds();
+    run(systemd_fds)
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn run(systemd_fds: Result<i32>) -> Result<()> {
     let opt = Opt::from_args();
+    if let Some(Command::Replay { file, speed, max_wait }) = &opt.command {
+        return recording::replay(file, *speed, *max_wait).await;
+    }
+
+    let mut config = Config::load(opt.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    opt.apply(&mut config);
+    let validation = config.validate();
+
+    let warnings = config.warnings();
 
-    if opt.help {
-        Opt::clap().write_long_help(&mut std::io::stdout()).unwrap();
+    if let Some(Command::Config(ConfigCommand::Dump)) = opt.command {
+        print!("{}", config.dump());
+        if !warnings.is_empty() {
+            eprintln!("\nWarnings:");
+            warnings.iter().for_each(|w| eprintln!("  {}", w));
+        }
+        if let Err(errors) = validation {
+            eprintln!("\nThis configuration would not start:");
+            errors.iter().for_each(|e| eprintln!("  {}", e));
+        }
         return Ok(());
     }
+    if let Err(errors) = validation {
+        eprintln!("Invalid configuration:");
+        errors.iter().for_each(|e| eprintln!("  {}", e));
+        std::process::exit(2);
+    }
+
+    let keymap = config.keymap().unwrap_or_default();
+    let magazine = Magazine::try_from_directory(&config.magazine_path()).unwrap_or_else(|e| {
+        eprintln!("Invalid configuration:");
+        eprintln!("  magazine: {}", e);
+        std::process::exit(2);
+    });
+
+    let background = parse_color(&config.theme.background).unwrap_or(Color::Black);
+    if let Some(Command::Export { format, out }) = &opt.command {
+        export::run(&magazine, *format, background, out)?;
+        println!("Exported {:?} to {:?}", config.magazine_path(), out);
+        return Ok(());
+    }
+    let handler = ZineHandler::new(magazine)
+        .with_background(background)
+        .with_keymap(keymap.clone());
 
-    let magazine = Magazine::from_directory(Path::new(&opt.magazine_path));
+    // The preview owns the terminal, so it runs before any logging is set up to write there
+    if let Some(Command::Preview) = opt.command {
+        return preview::run(handler, &config.magazine_path());
+    }
 
-    let handler = ZineHandler::new(magazine);
-    let server = telnet::TelnetServer::new(format!("{}:{}", opt.ip, opt.port).as_str(), handler).await?;
-    server.run().await
-}
\ No newline at end of file
+    logging::init(&config.logging).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    recording::init(&config.recording)?;
+    bookmarks::init(&config.bookmarks)?;
+    accounts::init(&config.accounts)?;
+    comments::init(&config.comments)?;
+    chat::init(&config.chat);
+    for warning in &warnings {
+        tracing::warn!("{}", warning);
+    }
+    if let Some(magazine_keymap) = handler.shelf().read().unwrap_or_else(|poisoned| poisoned.into_inner()).keymap.as_ref() {
+        for conflict in magazine_keymap.apply(&keymap).map(|keymap| keymap.conflicts()).unwrap_or_default() {
+            tracing::warn!("Magazine keymap: {}", conflict);
+        }
+    }
+
+    // Every issue in the library, for browsing tags across them and the finger plan's latest
+    // issue. The one being served comes from the shelf instead so a reload shows
+    let library = zine::load_library(&config.library);
+    let served = config.magazine_path().canonicalize().ok();
+    let latest = library.last().filter(|(path, _)| path.canonicalize().ok() != served).map(|(_, issue)| issue.title.clone());
+    let back_issues = library.into_iter().filter(|(path, _)| path.canonicalize().ok() != served).map(|(_, issue)| issue).collect();
+    let handler = handler.with_back_issues(back_issues);
+
+    let limits = SessionLimits::new(config.limits.max_connections, config.timeouts.idle_secs.map(Duration::from_secs));
+    let listen = &config.listen;
+    let addrs_for = |port: u16| listen.ip.iter().map(|ip| host_port(ip, port)).collect::<Vec<_>>();
+    let ssh_server = match listen.ssh_port {
+        Some(ssh_port) => Some(
+            ssh::SshServer::new(&addrs_for(ssh_port), &listen.ssh_host_key, handler.clone())
+                .await?
+                .with_proxy_protocol(config.proxy_settings())
+                .with_limits(limits.clone()),
+        ),
+        None => None,
+    };
+    let web_server = match listen.http_port {
+        Some(http_port) => Some(web::WebServer::new(&addrs_for(http_port), handler.clone()).await?.with_limits(limits.clone())),
+        None => None,
+    };
+    let gopher_server = match listen.gopher_port {
+        Some(gopher_port) => Some(gopher::GopherServer::new(&addrs_for(gopher_port), handler.shelf(), &listen.gopher_host, gopher_port).await?),
+        None => None,
+    };
+    let gemini_server = match listen.gemini_port {
+        Some(gemini_port) => Some(
+            gemini::GeminiServer::new(&addrs_for(gemini_port), &listen.gemini_cert, &listen.gemini_key, &listen.gemini_host, handler.shelf()).await?,
+        ),
+        None => None,
+    };
+    let finger_server = match listen.finger_port {
+        Some(finger_port) => Some(finger::FingerServer::new(&addrs_for(finger_port), config.finger.plan.as_deref(), handler.shelf(), latest).await?),
+        None => None,
+    };
+    let metrics_server = match listen.metrics_port {
+        Some(metrics_port) => Some(metrics::MetricsServer::new(&host_port(&listen.metrics_ip, metrics_port)).await?),
+        None => None,
+    };
+    let admin_server = match &config.admin.socket {
+        Some(path) => Some(admin::AdminServer::bind(path, handler.shelf(), config.magazine_path())?),
+        None => None,
+    };
+
+    let mut listeners = Vec::new();
+    if listen.listen_fds {
+        listeners.extend(Listener::from_systemd(systemd_fds?)?);
+    } else {
+        for ip in &listen.ip {
+            listeners.push(Listener::bind_tcp(ip, listen.port).await?);
+        }
+    }
+    for path in &listen.unix_sockets {
+        listeners.push(Listener::bind_unix(path)?);
+    }
+    let mut server = telnet::TelnetServer::from_listeners(listeners, handler)
+        .with_proxy_protocol(config.proxy_settings())
+        .with_limits(limits);
+    if let Some(tls_port) = listen.tls_port {
+        for addr in addrs_for(tls_port) {
+            server = server.with_tls(&addr, &listen.tls_cert, &listen.tls_key).await?;
+        }
+    }
+    tokio::try_join!(
+        server.run(),
+        run_optional(ssh_server.map(|s| s.run())),
+        run_optional(web_server.map(|s| s.run())),
+        run_optional(gopher_server.map(|s| s.run())),
+        run_optional(gemini_server.map(|s| s.run())),
+        run_optional(finger_server.map(|s| s.run())),
+        run_optional(metrics_server.map(|s| s.run())),
+        run_optional(admin_server.map(|s| s.run())),
+    )?;
+    Ok(())
+}
+
+// Run an optional frontend alongside the Telnet server
+async fn run_optional<F: Future<Output = Result<()>>>(server: Option<F>) -> Result<()> {
+    match server {
+        Some(server) => server.await,
+        None => Ok(()),
+    }
+}
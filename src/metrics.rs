@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{Error, Result};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use tracing::info;
+
+// Upper bounds of the session duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 9] = [5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 900.0, 1800.0, 3600.0];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+// Define the Family struct that holds one metric's value for every label combination seen so far
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    values: Mutex<BTreeMap<String, f64>>,
+}
+
+impl Family {
+    const fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Family { name, help, kind, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn add(&self, labels: &[(&str, &str)], amount: f64) {
+        let key = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut values = self.values.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *values.entry(key).or_insert(0.0) += amount;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+        let values = self.values.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (labels, value) in values.iter() {
+            let _ = writeln!(out, "{}{{{}}} {}", self.name, labels, value);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+struct Metrics {
+    active_sessions: Family,
+    connections: Family,
+    rejected: Family,
+    bytes_sent: Family,
+    page_views: Family,
+    negotiations: Family,
+    durations: Mutex<Histogram>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            active_sessions: Family::new("telzine_active_sessions", "gauge", "Readers currently connected"),
+            connections: Family::new("telzine_connections_total", "counter", "Sessions started"),
+            rejected: Family::new("telzine_rejected_connections_total", "counter", "Connections dropped before a session started"),
+            bytes_sent: Family::new("telzine_bytes_sent_total", "counter", "Bytes written to readers"),
+            page_views: Family::new("telzine_page_views_total", "counter", "Pages opened by readers"),
+            negotiations: Family::new("telzine_negotiations_total", "counter", "Finished sessions by terminal type and whether a window size was reported"),
+            durations: Mutex::new(Histogram::default()),
+        }
+    }
+}
+
+pub(crate) fn connected(frontend: &str) {
+    METRICS.connections.add(&[("frontend", frontend)], 1.0);
+    METRICS.active_sessions.add(&[("frontend", frontend)], 1.0);
+}
+
+pub(crate) fn disconnected(frontend: &str, duration: Duration, terminal: &str, window_size: bool) {
+    METRICS.active_sessions.add(&[("frontend", frontend)], -1.0);
+    let window_size = if window_size { "reported" } else { "missing" };
+    METRICS
+        .negotiations
+        .add(&[("frontend", frontend), ("terminal", terminal), ("window_size", window_size)], 1.0);
+
+    let seconds = duration.as_secs_f64();
+    let mut histogram = METRICS.durations.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(DURATION_BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    histogram.count += 1;
+    histogram.sum += seconds;
+}
+
+// Reasons are "full", "proxy" (bad or missing PROXY header), "tls" (failed handshake) and "timeout" (handshake too slow)
+pub(crate) fn rejected(frontend: &str, reason: &str) {
+    METRICS.rejected.add(&[("frontend", frontend), ("reason", reason)], 1.0);
+}
+
+pub(crate) fn sent(frontend: &str, bytes: usize) {
+    METRICS.bytes_sent.add(&[("frontend", frontend)], bytes as f64);
+}
+
+// Section and page are 1-based, as readers see them
+pub(crate) fn page_view(section: usize, page: usize) {
+    METRICS.page_views.add(&[("section", &section.to_string()), ("page", &page.to_string())], 1.0);
+}
+
+/// Every metric in the Prometheus text exposition format
+pub(crate) fn render() -> String {
+    let mut out = String::new();
+    for family in [
+        &METRICS.active_sessions,
+        &METRICS.connections,
+        &METRICS.rejected,
+        &METRICS.bytes_sent,
+        &METRICS.page_views,
+        &METRICS.negotiations,
+    ] {
+        family.render(&mut out);
+    }
+
+    let name = "telzine_session_duration_seconds";
+    let histogram = METRICS.durations.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _ = writeln!(out, "# HELP {} How long readers stayed connected", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for (count, bound) in histogram.buckets.iter().zip(DURATION_BUCKETS) {
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+    let _ = writeln!(out, "{}_sum {}", name, histogram.sum);
+    let _ = writeln!(out, "{}_count {}", name, histogram.count);
+    out
+}
+
+// Label values are quoted, so backslashes, quotes and newlines need escaping
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Define the MetricsServer struct that serves /metrics for a Prometheus scraper
+pub(crate) struct MetricsServer {
+    listener: std::net::TcpListener,
+}
+
+impl MetricsServer {
+    pub async fn new(addr: &str) -> Result<Self> {
+        let listener = tokio::net::TcpListener::bind(addr).await?.into_std()?;
+        Ok(MetricsServer { listener })
+    }
+
+    pub async fn run(self) -> Result<()> {
+        info!("Serving metrics on: http://{}/metrics", self.listener.local_addr()?);
+        let app = Router::new().route("/metrics", get(metrics));
+        axum::Server::from_tcp(self.listener)
+            .map_err(Error::other)?
+            .serve(app.into_make_service())
+            .await
+            .map_err(Error::other)
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], render())
+}
@@ -0,0 +1,128 @@
+use std::io::{stdout, Result, Stdout, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Stylize;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::line_editor::{KeyEvent, LineEditor};
+use crate::telnet::TelnetHandler;
+use crate::zine::Magazine;
+use crate::zine_handler::{Shelf, ZineHandler};
+
+// How often the magazine directory is checked for saved changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Puts the terminal back the way we found it, however the preview ends
+struct RawTerminal {
+    out: Stdout,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        execute!(out, EnterAlternateScreen)?;
+        Ok(RawTerminal { out })
+    }
+
+    // Pages are written with bare newlines, which raw mode doesn't return the cursor for
+    fn write(&mut self, output: &str) -> Result<()> {
+        let output = output.replace("\r\n", "\n").replace('\n', "\r\n");
+        self.out.write_all(output.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs the reader experience in this terminal with no server involved, reloading the
+/// magazine whenever one of its files is saved
+pub fn run(mut handler: ZineHandler, magazine_path: &Path) -> Result<()> {
+    let shelf = handler.shelf();
+    let mut editor = LineEditor::new();
+    let mut modified = last_modified(magazine_path);
+    let mut term = RawTerminal::enter()?;
+
+    let (width, height) = terminal::size()?;
+    handler.on_resize(width, height);
+    term.write(&handler.on_connect())?;
+
+    loop {
+        if event::poll(WATCH_INTERVAL)? {
+            let keys = match event::read()? {
+                Event::Resize(width, height) => {
+                    handler.on_resize(width, height);
+                    continue;
+                }
+                Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                    KeyCode::Char('c' | 'd') if key.modifiers.contains(KeyModifiers::CONTROL) => "\x03".to_string(),
+                    KeyCode::Char(c) => c.to_string(),
+                    KeyCode::Enter => "\r".to_string(),
+                    KeyCode::Backspace => "\x7f".to_string(),
+                    KeyCode::Esc => "\x03".to_string(),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            for event in editor.feed(&keys) {
+                match event {
+                    KeyEvent::Echo(echo) => term.write(&echo)?,
+                    KeyEvent::Line(line) => {
+                        let output = handler.handle(line.trim());
+                        if output == handler.quit() {
+                            return Ok(());
+                        }
+                        term.write(&output)?;
+                    }
+                    KeyEvent::Interrupt => return Ok(()),
+                }
+            }
+            continue;
+        }
+
+        let latest = last_modified(magazine_path);
+        if latest != modified {
+            modified = latest;
+            match reload(&shelf, magazine_path) {
+                Ok(()) => term.write(&handler.redraw())?,
+                Err(reason) => {
+                    let (_, height) = terminal::size()?;
+                    queue!(term.out, MoveTo(0, height.saturating_sub(1)), Clear(ClearType::CurrentLine))?;
+                    term.write(&format!("{}", format!("Reload failed: {}", reason).white().on_red()))?;
+                }
+            }
+        }
+    }
+}
+
+// Swap in the saved magazine, keeping the one on screen when the saved one doesn't load
+fn reload(shelf: &Shelf, magazine_path: &Path) -> std::result::Result<(), String> {
+    let loaded = Magazine::try_from_directory(magazine_path)?;
+    *shelf.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(loaded);
+    Ok(())
+}
+
+// The newest modification time of any file in the magazine directory
+fn last_modified(directory: &Path) -> Option<SystemTime> {
+    std::fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if metadata.is_dir() {
+                last_modified(&entry.path())
+            } else {
+                metadata.modified().ok()
+            }
+        })
+        .max()
+}
@@ -0,0 +1,351 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, timeout_at, Instant};
+
+// Load balancers send the header right after connecting, but telnet clients wait for the
+// server to speak first, so "optional" can only wait briefly before assuming a direct client
+const OPTIONAL_TIMEOUT: Duration = Duration::from_millis(500);
+const REQUIRED_TIMEOUT: Duration = Duration::from_secs(5);
+// The whole header, once it has started, must arrive within this long of connecting
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+// Whether listeners expect a PROXY protocol (v1 or v2) header before the client's data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+    Off,
+    Optional,
+    Required,
+}
+
+impl FromStr for ProxyProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(ProxyProtocol::Off),
+            "optional" => Ok(ProxyProtocol::Optional),
+            "required" => Ok(ProxyProtocol::Required),
+            _ => Err(format!("Invalid PROXY protocol mode {:?}, expected off, optional or required", s)),
+        }
+    }
+}
+
+/// An address block like 10.0.0.0/8 or 2001:db8::/32; a bare address is a block of one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (ip, prefix) = s.trim().split_once('/').map(|(ip, prefix)| (ip, Some(prefix))).unwrap_or((s.trim(), None));
+        let network: IpAddr = ip.parse().map_err(|_| format!("{:?} is not an IP address or CIDR block", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|&prefix| prefix <= max).ok_or_else(|| format!("{:?} has an invalid prefix length", s))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 peers of a dual-stack socket show up as ::ffff:a.b.c.d
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// Define the ProxySettings struct with the PROXY protocol mode and the peers allowed to send headers
+#[derive(Clone, Debug)]
+pub(crate) struct ProxySettings {
+    pub mode: ProxyProtocol,
+    // Load balancers whose headers are believed, any peer when empty
+    pub trusted: Arc<Vec<Cidr>>,
+}
+
+impl Default for ProxySettings {
+    fn default() -> Self {
+        ProxySettings { mode: ProxyProtocol::Off, trusted: Arc::new(Vec::new()) }
+    }
+}
+
+impl ProxySettings {
+    pub fn new(mode: ProxyProtocol, trusted: Vec<Cidr>) -> Self {
+        ProxySettings { mode, trusted: Arc::new(trusted) }
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.trusted.is_empty() || self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+enum Detected {
+    V1,
+    V2,
+    None,
+}
+
+/// Consumes a PROXY protocol header from the start of the stream if there is one and returns
+/// the real client address, falling back to the peer address when the header is absent or
+/// describes a local/unknown connection. Peers that aren't trusted proxies are taken at their word
+/// in optional mode, without looking for a header, and turned away in required mode
+pub(crate) async fn read_client_addr(stream: &mut TcpStream, settings: &ProxySettings, peer: SocketAddr) -> Result<SocketAddr> {
+    let wait = match settings.mode {
+        ProxyProtocol::Off => return Ok(peer),
+        ProxyProtocol::Optional if !settings.trusts(peer.ip()) => return Ok(peer),
+        ProxyProtocol::Required if !settings.trusts(peer.ip()) => {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Connection is not from a trusted proxy"));
+        }
+        ProxyProtocol::Optional => OPTIONAL_TIMEOUT,
+        ProxyProtocol::Required => REQUIRED_TIMEOUT,
+    };
+
+    // A client that starts a header and stalls must not hold the connection open for good
+    let deadline = Instant::now() + HEADER_TIMEOUT;
+    let stalled = |_| Error::new(ErrorKind::TimedOut, "PROXY protocol header did not arrive in time");
+    let detected = timeout(wait, detect(stream)).await.unwrap_or(Ok(Detected::None))?;
+    let addr = match detected {
+        Detected::V1 => timeout_at(deadline, read_v1(stream)).await.map_err(stalled)??,
+        Detected::V2 => timeout_at(deadline, read_v2(stream)).await.map_err(stalled)??,
+        Detected::None if settings.mode == ProxyProtocol::Required => {
+            return Err(Error::new(ErrorKind::InvalidData, "Connection did not start with a PROXY protocol header"));
+        }
+        Detected::None => None,
+    };
+    Ok(addr.unwrap_or(peer))
+}
+
+// Peek at the first bytes until they either match or rule out one of the two signatures
+async fn detect(stream: &mut TcpStream) -> Result<Detected> {
+    let mut buf = [0u8; 12];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before PROXY protocol header"));
+        }
+        let seen = &buf[..n];
+        let v1 = V1_PREFIX.starts_with(&seen[..n.min(V1_PREFIX.len())]);
+        let v2 = V2_SIGNATURE.starts_with(seen);
+        match (v1, v2) {
+            (true, _) if n >= V1_PREFIX.len() => return Ok(Detected::V1),
+            (_, true) if n >= V2_SIGNATURE.len() => return Ok(Detected::V2),
+            (false, false) => return Ok(Detected::None),
+            // Still ambiguous, give the rest of the header a moment to arrive
+            _ => sleep(Duration::from_millis(10)).await,
+        }
+    }
+}
+
+// "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n" or "PROXY UNKNOWN ...\r\n"
+async fn read_v1(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<SocketAddr>> {
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.first() != Some(&"PROXY") {
+        return Err(invalid("Malformed PROXY v1 header"));
+    }
+    match fields.as_slice() {
+        [_, "UNKNOWN", ..] => Ok(None),
+        [_, family @ ("TCP4" | "TCP6"), src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("Invalid PROXY v1 source address"))?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                return Err(invalid("PROXY v1 source address does not match its family"));
+            }
+            let port: u16 = src_port.parse().map_err(|_| invalid("Invalid PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("Malformed PROXY v1 header")),
+    }
+}
+
+// 12 byte signature, version/command, family/protocol, 2 byte length, then the addresses and TLVs
+async fn read_v2(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<SocketAddr>> {
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header).await?;
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await?;
+
+    if header[..12] != *V2_SIGNATURE {
+        return Err(invalid("Malformed PROXY v2 signature"));
+    }
+    if header[12] >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY v2 version"));
+    }
+    match header[12] & 0x0f {
+        // LOCAL connections are health checks from the proxy itself, which speaks for itself
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(invalid("Unsupported PROXY v2 command")),
+    }
+    // UNSPEC, STREAM or DGRAM
+    if header[13] & 0x0f > 2 {
+        return Err(invalid("Unsupported PROXY v2 transport protocol"));
+    }
+
+    match header[13] >> 4 {
+        // AF_INET
+        1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6
+        2 if body.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        }
+        1 | 2 => Err(invalid("Truncated PROXY v2 address block")),
+        // AF_UNSPEC and AF_UNIX carry no useful client address
+        0 | 3 => Ok(None),
+        _ => Err(invalid("Unsupported PROXY v2 address family")),
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    #[tokio::test]
+    async fn v1_source_address() {
+        let mut header: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 23\r\nhello";
+        assert_eq!(read_v1(&mut header).await.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        // Only the header is consumed
+        assert_eq!(header, b"hello");
+
+        let mut header: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 23\r\n";
+        assert_eq!(read_v1(&mut header).await.unwrap(), Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let mut header: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_v1(&mut header).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v1_rejects_malformed_headers() {
+        for header in [
+            &b"PROXY TCP4 2001:db8::1 192.0.2.2 1 2\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 port 2\r\n",
+            b"PROXY TCP4 192.0.2.1\r\n",
+            b"PROXI TCP4 192.0.2.1 192.0.2.2 1 2\r\n",
+        ] {
+            let mut header = header;
+            assert!(read_v1(&mut header).await.is_err());
+        }
+        let long = format!("PROXY TCP4 {}\r\n", "1".repeat(V1_MAX_LEN));
+        assert!(read_v1(&mut long.as_bytes()).await.is_err());
+        // Cut off before the line ends
+        assert!(read_v1(&mut &b"PROXY TCP4 192.0.2.1"[..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn v2_source_address() {
+        let body = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0, 23];
+        let header = v2(1, 0x11, &body);
+        assert_eq!(read_v2(&mut header.as_slice()).await.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+
+        let mut body = [0u8; 36];
+        body[0] = 0x20;
+        body[1] = 0x01;
+        body[15] = 1;
+        body[32..34].copy_from_slice(&4000u16.to_be_bytes());
+        let header = v2(1, 0x21, &body);
+        assert_eq!(read_v2(&mut header.as_slice()).await.unwrap(), Some("[2001::1]:4000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn v2_local_falls_back_to_the_peer() {
+        let header = v2(0, 0x00, &[]);
+        assert_eq!(read_v2(&mut header.as_slice()).await.unwrap(), None);
+        // Whatever addresses a LOCAL header carries are ignored
+        let header = v2(0, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0, 1, 0, 2]);
+        assert_eq!(read_v2(&mut header.as_slice()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v2_rejects_unknown_commands_and_families() {
+        let body = [192, 0, 2, 1, 198, 51, 100, 1, 0, 1, 0, 23];
+        for header in [v2(2, 0x11, &body), v2(1, 0x41, &body), v2(1, 0x13, &body), v2(1, 0x11, &body[..8])] {
+            assert!(read_v2(&mut header.as_slice()).await.is_err());
+        }
+    }
+
+    #[test]
+    fn cidr_blocks() {
+        let block: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(block.contains("10.1.2.3".parse().unwrap()));
+        assert!(block.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!block.contains("11.0.0.1".parse().unwrap()));
+        assert!(!block.contains("2001:db8::1".parse().unwrap()));
+
+        let single: Cidr = "192.0.2.7".parse().unwrap();
+        assert!(single.contains("192.0.2.7".parse().unwrap()));
+        assert!(!single.contains("192.0.2.8".parse().unwrap()));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8:ffff::1".parse().unwrap()));
+        assert!(!v6.contains("2001:db9::1".parse().unwrap()));
+
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("203.0.113.9".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("load-balancer".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn only_trusted_peers_may_send_headers() {
+        let anyone = ProxySettings::new(ProxyProtocol::Optional, Vec::new());
+        assert!(anyone.trusts("203.0.113.9".parse().unwrap()));
+        let balancers = ProxySettings::new(ProxyProtocol::Optional, vec!["10.0.0.0/8".parse().unwrap()]);
+        assert!(balancers.trusts("10.0.0.5".parse().unwrap()));
+        assert!(!balancers.trusts("203.0.113.9".parse().unwrap()));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use chrono::prelude::*;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+
+use crate::config::RecordingConfig;
+
+// Terminal size assumed when the client never reports one
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+static RECORDING: OnceLock<RecordingConfig> = OnceLock::new();
+
+/// Turns on session recording when a directory is configured
+pub fn init(config: &RecordingConfig) -> Result<()> {
+    if let Some(directory) = &config.directory {
+        fs::create_dir_all(directory)
+            .map_err(|e| Error::new(e.kind(), format!("Failed to create recording directory {:?}: {}", directory, e)))?;
+        let _ = RECORDING.set(config.clone());
+    }
+    Ok(())
+}
+
+/// One session's output (and optionally input) being written as an asciicast v2 file.
+/// The header needs the terminal size and type, which clients negotiate right after
+/// connecting, so events are held back until the reader's first input.
+pub(crate) struct Recording {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    input: bool,
+    started: Instant,
+    timestamp: i64,
+    terminal: Option<String>,
+    size: Option<(u16, u16)>,
+    pending: Option<Vec<Value>>,
+}
+
+impl Recording {
+    // Open a recording for a new session, None when recording is off or the file can't be created
+    pub fn start(session: u64, frontend: &str) -> Option<Self> {
+        let config = RECORDING.get()?;
+        let now = Local::now();
+        let name = format!("{}-{}-{}.cast", now.format("%Y%m%d-%H%M%S"), session, frontend);
+        let path = config.directory.as_ref()?.join(name);
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::warn!("Failed to create recording {:?}: {}", path, e);
+                return None;
+            }
+        };
+        Some(Recording {
+            writer: BufWriter::new(file),
+            path,
+            input: config.input,
+            started: Instant::now(),
+            timestamp: now.timestamp(),
+            terminal: None,
+            size: None,
+            pending: Some(Vec::new()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_terminal(&mut self, terminal: &str) {
+        self.terminal = Some(terminal.to_string());
+    }
+
+    pub fn output(&mut self, data: &str) {
+        if !data.is_empty() {
+            self.event("o", data.to_string());
+        }
+    }
+
+    pub fn input(&mut self, data: &str) {
+        self.write_header();
+        if self.input {
+            self.event("i", data.to_string());
+        }
+    }
+
+    pub fn resize(&mut self, width: u16, height: u16) {
+        if self.size == Some((width, height)) {
+            return;
+        }
+        self.size = Some((width, height));
+        if self.pending.is_none() {
+            self.event("r", format!("{}x{}", width, height));
+        }
+    }
+
+    fn event(&mut self, kind: &str, data: String) {
+        let event = json!([(self.started.elapsed().as_secs_f64() * 1e6).round() / 1e6, kind, data]);
+        match &mut self.pending {
+            Some(pending) => pending.push(event),
+            None => self.write_line(&event),
+        }
+    }
+
+    // Write the header and anything held back while waiting for it, once
+    fn write_header(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let (width, height) = self.size.unwrap_or(DEFAULT_SIZE);
+        let mut header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": self.timestamp,
+        });
+        if let Some(terminal) = &self.terminal {
+            header["env"] = json!({ "TERM": terminal });
+        }
+        self.write_line(&header);
+        for event in pending {
+            self.write_line(&event);
+        }
+    }
+
+    fn write_line(&mut self, value: &Value) {
+        let _ = writeln!(self.writer, "{}", value);
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.write_header();
+        let _ = self.writer.flush();
+    }
+}
+
+/// Plays an asciicast v2 recording back to stdout in real time. Pauses longer than
+/// `max_wait` seconds are shortened, and `speed` scales the whole playback.
+pub async fn replay(path: &Path, speed: f64, max_wait: Option<f64>) -> Result<()> {
+    if speed <= 0.0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "--speed must be greater than 0"));
+    }
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: Value = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).map_err(|e| invalid(format!("Invalid asciicast header: {}", e)))?,
+        None => return Err(invalid("Recording is empty".to_string())),
+    };
+    if header["version"] != 2 {
+        return Err(invalid("Only asciicast v2 recordings can be replayed".to_string()));
+    }
+    if let (Some(width), Some(height), Ok((columns, rows))) = (header["width"].as_u64(), header["height"].as_u64(), crossterm::terminal::size()) {
+        if width > columns as u64 || height > rows as u64 {
+            eprintln!("Recorded at {}x{}, this terminal is {}x{}, the output may not line up", width, height, columns, rows);
+        }
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut previous = 0.0;
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: (f64, String, String) = serde_json::from_str(&line).map_err(|e| invalid(format!("Invalid asciicast event: {}", e)))?;
+        let (time, kind, data) = event;
+        let mut wait = (time - previous).max(0.0);
+        if let Some(max_wait) = max_wait {
+            wait = wait.min(max_wait);
+        }
+        previous = time;
+        sleep(Duration::from_secs_f64(wait / speed)).await;
+        // Input and resize events don't change what's on screen
+        if kind == "o" {
+            stdout.write_all(data.as_bytes())?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
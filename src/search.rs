@@ -0,0 +1,189 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crossterm::style::Stylize;
+
+use crate::ansi;
+use crate::zine::Section;
+
+// Most hits listed for one search
+const MAX_HITS: usize = 20;
+// Words of context shown either side of the first match in a snippet
+const CONTEXT_BEFORE: usize = 5;
+const CONTEXT_AFTER: usize = 8;
+
+// One page as searched: its text with the styling stripped and where each word in it starts and ends
+#[derive(Clone, Debug)]
+struct IndexedPage {
+    section: usize,
+    page: usize,
+    text: String,
+    words: Vec<(usize, usize)>,
+}
+
+// A page matching a search, 0-based like the rest of the handler, with a snippet of the text
+// around the first match and every match in it highlighted
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub section: usize,
+    pub page: usize,
+    pub snippet: String,
+}
+
+/// Full-text index over every page of a magazine, built once when it loads. Words are
+/// matched case-insensitively and by prefix, so "tel" finds "Telnet".
+#[derive(Clone, Debug, Default)]
+pub struct SearchIndex {
+    pages: Vec<IndexedPage>,
+    // Lowercased word to the pages it appears on
+    words: BTreeMap<String, BTreeSet<usize>>,
+}
+
+impl SearchIndex {
+    pub fn build(sections: &[Section]) -> Self {
+        let mut index = SearchIndex::default();
+        for (section_index, section) in sections.iter().enumerate() {
+            for (page_index, page) in section.pages.iter().enumerate() {
+                index.add(section_index, page_index, ansi::strip(&page.text));
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, section: usize, page: usize, text: String) {
+        let words = words(&text);
+        let id = self.pages.len();
+        for &(start, end) in &words {
+            self.words.entry(text[start..end].to_lowercase()).or_default().insert(id);
+        }
+        self.pages.push(IndexedPage { section, page, text, words });
+    }
+
+    /// Pages containing every word of the query, those with the most matches first
+    pub fn search(&self, query: &str) -> Vec<Hit> {
+        let terms: Vec<String> = words(query).into_iter().map(|(start, end)| query[start..end].to_lowercase()).collect();
+        let Some(mut matching) = terms.first().map(|term| self.pages_matching(term)) else {
+            return Vec::new();
+        };
+        for term in &terms[1..] {
+            let pages = self.pages_matching(term);
+            matching.retain(|id| pages.contains(id));
+        }
+
+        let mut hits: Vec<(usize, &IndexedPage)> = matching
+            .into_iter()
+            .map(|id| {
+                let page = &self.pages[id];
+                (page.words.iter().filter(|&&(start, end)| is_match(&page.text[start..end], &terms)).count(), page)
+            })
+            .collect();
+        hits.sort_by(|(a, _), (b, _)| b.cmp(a));
+        hits.into_iter()
+            .take(MAX_HITS)
+            .map(|(_, page)| Hit { section: page.section, page: page.page, snippet: snippet(page, &terms) })
+            .collect()
+    }
+
+    // Pages with a word starting with the term
+    fn pages_matching(&self, term: &str) -> BTreeSet<usize> {
+        self.words
+            .range(term.to_string()..)
+            .take_while(|(word, _)| word.starts_with(term))
+            .flat_map(|(_, pages)| pages.iter().copied())
+            .collect()
+    }
+}
+
+// Byte ranges of the runs of letters and digits in the text
+fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
+
+fn is_match(word: &str, terms: &[String]) -> bool {
+    let word = word.to_lowercase();
+    terms.iter().any(|term| word.starts_with(term.as_str()))
+}
+
+// A few words either side of the first match on one line, with the matches shown in reverse video
+fn snippet(page: &IndexedPage, terms: &[String]) -> String {
+    let first = page.words.iter().position(|&(start, end)| is_match(&page.text[start..end], terms)).unwrap_or(0);
+    let from = first.saturating_sub(CONTEXT_BEFORE);
+    let to = (first + CONTEXT_AFTER).min(page.words.len().saturating_sub(1));
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push_str("...");
+    }
+    let mut previous_end = page.words.get(from).map(|&(start, _)| start).unwrap_or(0);
+    for &(start, end) in page.words.get(from..=to).unwrap_or_default() {
+        // Keep the punctuation between words but not the line breaks
+        let mut spaced = false;
+        for c in page.text[previous_end..start].chars() {
+            if !c.is_whitespace() {
+                snippet.push(c);
+            } else if !spaced {
+                snippet.push(' ');
+            }
+            spaced = c.is_whitespace();
+        }
+        let word = &page.text[start..end];
+        if is_match(word, terms) {
+            snippet.push_str(&format!("{}", word.reverse()));
+        } else {
+            snippet.push_str(word);
+        }
+        previous_end = end;
+    }
+    if to + 1 < page.words.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(pages: &[&str]) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for (page, text) in pages.iter().enumerate() {
+            index.add(0, page, text.to_string());
+        }
+        index
+    }
+
+    fn pages(hits: &[Hit]) -> Vec<usize> {
+        hits.iter().map(|hit| hit.page).collect()
+    }
+
+    #[test]
+    fn most_matches_first() {
+        let index = index(&["telnet once", "Telnet, telnet and TELNET", "nothing here", "telnet twice: telnet"]);
+        assert_eq!(pages(&index.search("telnet")), [1, 3, 0]);
+        // Prefixes and any case match, and every word has to be on the page
+        assert_eq!(pages(&index.search("TEL")), [1, 3, 0]);
+        assert_eq!(pages(&index.search("telnet twice")), [3]);
+        assert_eq!(pages(&index.search("telnet gopher")), Vec::<usize>::new());
+        assert!(index.search("  ,. ").is_empty());
+    }
+
+    #[test]
+    fn snippets_highlight_matches_in_context() {
+        let index = index(&["one two three four five six seven\neight nine ten eleven twelve thirteen fourteen fifteen sixteen"]);
+        let hits = index.search("seven");
+        let expected = format!("...two three four five six {} eight nine ten eleven twelve thirteen fourteen fifteen...", "seven".reverse());
+        assert_eq!(hits[0].snippet, expected);
+    }
+}
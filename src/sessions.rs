@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::chat;
+use crate::listener::PeerAddr;
+
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::default()));
+
+// Instructions the admin console sends to a running session
+#[derive(Clone, Debug)]
+pub(crate) enum Control {
+    // Show the reader a message
+    Message(String),
+    // Show the reader a message and disconnect them
+    Kick(String),
+    // A line said in the chat room, for a reader who is in it
+    Chat(String),
+}
+
+impl Control {
+    // How the message is shown below whatever the reader is looking at
+    pub fn text(&self) -> String {
+        match self {
+            Control::Message(message) => format!("\r\n\r\n*** {} ***\r\n", message),
+            // The reader may be in the chat, whose split screen would outlive the connection
+            Control::Kick(message) => format!("{}\r\n\r\n*** {} ***\r\n", chat::UNSPLIT, message),
+            // The chat keeps the reader's prompt on the bottom line, under the scrolling part of
+            // the screen, so the line is added to the bottom of that without losing their place
+            Control::Chat(line) => format!("\x1b7\x1b[2A\r\n{}\x1b8", line),
+        }
+    }
+}
+
+// What the admin console knows about one connected reader
+#[derive(Clone, Debug)]
+pub(crate) struct SessionInfo {
+    pub id: u64,
+    pub frontend: &'static str,
+    pub peer: PeerAddr,
+    pub terminal: Option<String>,
+    pub size: Option<(u16, u16)>,
+    // 1-based section and page on screen
+    pub page: Option<(usize, usize)>,
+    // The account the reader signed in to
+    pub user: Option<String>,
+    pub started: Instant,
+    pub last_input: Instant,
+    pub kicked: bool,
+}
+
+#[derive(Default)]
+struct Registry {
+    sessions: BTreeMap<u64, (SessionInfo, UnboundedSender<Control>)>,
+    banned: BTreeSet<IpAddr>,
+}
+
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Add a session, returning the receiver its frontend should watch for admin instructions
+pub(crate) fn register(id: u64, frontend: &'static str, peer: PeerAddr) -> UnboundedReceiver<Control> {
+    let (sender, receiver) = unbounded_channel();
+    let now = Instant::now();
+    let info = SessionInfo {
+        id,
+        frontend,
+        peer,
+        terminal: None,
+        size: None,
+        page: None,
+        user: None,
+        started: now,
+        last_input: now,
+        kicked: false,
+    };
+    registry().sessions.insert(id, (info, sender));
+    receiver
+}
+
+pub(crate) fn update(id: u64, change: impl FnOnce(&mut SessionInfo)) {
+    if let Some((info, _)) = registry().sessions.get_mut(&id) {
+        change(info);
+    }
+}
+
+pub(crate) fn remove(id: u64) -> Option<SessionInfo> {
+    registry().sessions.remove(&id).map(|(info, _)| info)
+}
+
+// The IP address a session connected from, None for Unix sockets and sessions that are gone
+pub(crate) fn ip(id: u64) -> Option<IpAddr> {
+    registry().sessions.get(&id)?.0.peer.ip()
+}
+
+pub(crate) fn list() -> Vec<SessionInfo> {
+    registry().sessions.values().map(|(info, _)| info.clone()).collect()
+}
+
+// Returns false when there is no such session
+pub(crate) fn send(id: u64, control: Control) -> bool {
+    let mut registry = registry();
+    let Some((info, sender)) = registry.sessions.get_mut(&id) else {
+        return false;
+    };
+    if let Control::Kick(_) = control {
+        info.kicked = true;
+    }
+    sender.send(control).is_ok()
+}
+
+// Returns how many sessions the message went to
+pub(crate) fn broadcast(message: &str) -> usize {
+    // Readers in the chat get it as a line in the room, which keeps their screen in one piece
+    let chatting = chat::members();
+    registry()
+        .sessions
+        .iter()
+        .filter(|(id, (_, sender))| {
+            let control = match chatting.contains(id) {
+                true => Control::Chat(format!("*** {} ***", message)),
+                false => Control::Message(message.to_string()),
+            };
+            sender.send(control).is_ok()
+        })
+        .count()
+}
+
+// Refuse new connections from this address and kick the sessions already using it, returning how many were kicked
+pub(crate) fn ban(ip: IpAddr) -> usize {
+    registry().banned.insert(ip);
+    let ids: Vec<u64> = list().iter().filter(|info| info.peer.ip() == Some(ip)).map(|info| info.id).collect();
+    ids.into_iter().filter(|&id| send(id, Control::Kick("You have been banned.".to_string()))).count()
+}
+
+pub(crate) fn unban(ip: IpAddr) -> bool {
+    registry().banned.remove(&ip)
+}
+
+pub(crate) fn bans() -> Vec<IpAddr> {
+    registry().banned.iter().copied().collect()
+}
+
+pub(crate) fn is_banned(ip: IpAddr) -> bool {
+    registry().banned.contains(&ip)
+}
@@ -0,0 +1,304 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+use async_trait::async_trait;
+use russh::server::{Auth, Config, Handler, Msg, Session};
+use russh::{Channel, ChannelId, CryptoVec, MethodSet, Pty};
+use russh_keys::key::KeyPair;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tracing::{info, warn, Instrument};
+
+use crate::line_editor::{KeyEvent, LineEditor};
+use crate::logging::SessionLog;
+use crate::metrics;
+use crate::sessions::Control;
+use crate::proxy_protocol::ProxySettings;
+use crate::telnet::{client_addr, SessionLimits, TelnetHandler};
+
+// Define the SshServer struct that serves the same TelnetHandler to ssh clients
+pub(crate) struct SshServer<H: TelnetHandler> {
+    listeners: Vec<TcpListener>,
+    config: Config,
+    proxy_protocol: ProxySettings,
+    limits: SessionLimits,
+    handler: H,
+}
+
+impl<H: TelnetHandler + Send + Sync + 'static + Clone> SshServer<H> {
+    // Bind the listeners and load the host key, generating and persisting one on first start
+    pub async fn new(addrs: &[String], host_key_path: &Path, handler: H) -> Result<Self> {
+        let host_key = load_or_generate_host_key(host_key_path)?;
+        let config = Config {
+            // Anyone may read the zine, so accept "none" and any password
+            methods: MethodSet::NONE | MethodSet::PASSWORD,
+            auth_rejection_time: std::time::Duration::from_secs(1),
+            auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
+            keys: vec![host_key],
+            ..Default::default()
+        };
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            listeners.push(TcpListener::bind(addr).await?);
+        }
+        Ok(SshServer { listeners, config, proxy_protocol: ProxySettings::default(), limits: SessionLimits::default(), handler })
+    }
+
+    // Expect a PROXY protocol header on every connection, for running behind a load balancer
+    pub fn with_proxy_protocol(mut self, proxy_protocol: ProxySettings) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    // Share the connection cap and idle timeout with the other frontends
+    pub fn with_limits(mut self, limits: SessionLimits) -> Self {
+        self.config.inactivity_timeout = limits.idle;
+        self.limits = limits;
+        self
+    }
+
+    // Run the server and spawn a new russh session for each incoming connection
+    pub async fn run(self) -> Result<()> {
+        let config = Arc::new(self.config);
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            info!("Listening for SSH on: {}", listener.local_addr()?);
+            accept_loops.spawn(accept_loop(listener, config.clone(), self.proxy_protocol.clone(), self.limits.clone(), self.handler.clone()));
+        }
+        while let Some(result) = accept_loops.join_next().await {
+            result.map_err(Error::other)??;
+        }
+        Ok(())
+    }
+}
+
+async fn accept_loop<H: TelnetHandler + Clone>(
+    listener: TcpListener,
+    config: Arc<Config>,
+    proxy_protocol: ProxySettings,
+    limits: SessionLimits,
+    handler: H,
+) -> Result<()> {
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let config = config.clone();
+        let handler = handler.clone();
+        let limits = limits.clone();
+        let proxy_protocol = proxy_protocol.clone();
+
+        tokio::spawn(async move {
+            // Read any PROXY header before taking a slot, so a stalled one never holds it
+            let Some(addr) = client_addr(&mut stream, &proxy_protocol, peer, "ssh").await else {
+                return;
+            };
+            // There's no channel to explain ourselves on before the handshake, so a full server just hangs up
+            let Some(_permit) = limits.try_acquire() else {
+                warn!(peer = %addr, "Turned away: server is full");
+                metrics::rejected("ssh", "full");
+                return;
+            };
+            let log = SessionLog::start("ssh", addr);
+            let span = log.span();
+            async move {
+                let result = match russh::server::run_stream(config, stream, SshSession::new(handler, log)).await {
+                    Ok(running) => running.await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => {}
+                    // Clients usually just drop the TCP connection once their channel is closed
+                    Err(russh::Error::IO(ref e)) if e.kind() == ErrorKind::UnexpectedEof => {}
+                    Err(e) => warn!("Error handling SSH connection: {}", e),
+                }
+            }
+            .instrument(span)
+            .await
+        });
+    }
+}
+
+// Load the ssh host key from disk, or generate an ed25519 key and save it so clients see a stable fingerprint
+fn load_or_generate_host_key(path: &Path) -> Result<KeyPair> {
+    if path.exists() {
+        return russh_keys::load_secret_key(path, None)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to load host key {:?}: {}", path, e)));
+    }
+
+    let key = KeyPair::generate_ed25519()
+        .ok_or_else(|| Error::other("Failed to generate host key"))?;
+    let mut pem = Vec::new();
+    russh_keys::encode_pkcs8_pem(&key, &mut pem)
+        .map_err(|e| Error::other(format!("Failed to encode host key: {}", e)))?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, pem)?;
+    restrict_permissions(path)?;
+    info!("Generated new SSH host key at {:?}", path);
+    Ok(key)
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+// Define the SshSession struct that adapts one ssh connection onto a TelnetHandler
+struct SshSession<H: TelnetHandler> {
+    handler: H,
+    channel: Option<ChannelId>,
+    editor: LineEditor,
+    log: SessionLog,
+}
+
+impl<H: TelnetHandler> SshSession<H> {
+    fn new(mut handler: H, log: SessionLog) -> Self {
+        handler.on_session(log.id());
+        SshSession { handler, channel: None, editor: LineEditor::new(), log }
+    }
+
+    // Ssh ptys are in raw mode, so echo keystrokes and collect them into lines like a telnet client would
+    async fn handle_keys(&mut self, data: &[u8], channel: ChannelId, session: &mut Session) -> bool {
+        let keys = String::from_utf8_lossy(data);
+        self.log.received(&keys);
+        for event in self.editor.feed(&keys) {
+            match event {
+                KeyEvent::Echo(echo) => self.send(channel, session, echo),
+                KeyEvent::Line(line) => {
+                    let mut output = self.handler.handle(line.trim());
+                    if let Some(settled) = self.handler.settle().await {
+                        output = settled;
+                    }
+                    self.log.observe(&self.handler);
+                    self.editor.hidden = self.handler.hide_input();
+                    if output == self.handler.quit() {
+                        self.close(channel, session);
+                        return false;
+                    }
+                    self.send(channel, session, output);
+                }
+                KeyEvent::Interrupt => {
+                    self.close(channel, session);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn close(&mut self, channel: ChannelId, session: &mut Session) {
+        self.log.set_reason("quit");
+        let goodbye = self.handler.on_quit();
+        self.send(channel, session, goodbye);
+        session.eof(channel);
+        session.close(channel);
+    }
+
+    // Write to the reader, counting the bytes for the metrics and recording them
+    fn send(&mut self, channel: ChannelId, session: &mut Session, output: String) {
+        self.log.sent(&output);
+        session.data(channel, CryptoVec::from(output));
+    }
+}
+
+#[async_trait]
+impl<H: TelnetHandler> Handler for SshSession<H> {
+    type Error = russh::Error;
+
+    async fn auth_none(self, _user: &str) -> std::result::Result<(Self, Auth), Self::Error> {
+        Ok((self, Auth::Accept))
+    }
+
+    async fn auth_password(self, _user: &str, _password: &str) -> std::result::Result<(Self, Auth), Self::Error> {
+        Ok((self, Auth::Accept))
+    }
+
+    async fn channel_open_session(mut self, channel: Channel<Msg>, session: Session) -> std::result::Result<(Self, bool, Session), Self::Error> {
+        // One reader per connection, so only the first session channel is served
+        if self.channel.is_some() {
+            return Ok((self, false, session));
+        }
+        self.channel = Some(channel.id());
+        Ok((self, true, session))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn pty_request(
+        mut self,
+        channel: ChannelId,
+        term: &str,
+        col_width: u32,
+        row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(Pty, u32)],
+        mut session: Session,
+    ) -> std::result::Result<(Self, Session), Self::Error> {
+        self.handler.on_resize(clamp_size(col_width), clamp_size(row_height));
+        self.log.set_terminal(term);
+        self.log.resized(clamp_size(col_width), clamp_size(row_height));
+        session.channel_success(channel);
+        Ok((self, session))
+    }
+
+    async fn window_change_request(
+        mut self,
+        _channel: ChannelId,
+        col_width: u32,
+        row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        session: Session,
+    ) -> std::result::Result<(Self, Session), Self::Error> {
+        self.handler.on_resize(clamp_size(col_width), clamp_size(row_height));
+        self.log.resized(clamp_size(col_width), clamp_size(row_height));
+        Ok((self, session))
+    }
+
+    async fn shell_request(mut self, channel: ChannelId, mut session: Session) -> std::result::Result<(Self, Session), Self::Error> {
+        session.channel_success(channel);
+        let cover = self.handler.on_connect();
+        self.send(channel, &mut session, cover);
+        // Admin messages arrive outside of any client request, so they go through a handle of their own
+        if let Some(mut control) = self.log.take_control() {
+            let handle = session.handle();
+            let relay = async move {
+                while let Some(control) = control.recv().await {
+                    let _ = handle.data(channel, CryptoVec::from(control.text())).await;
+                    if let Control::Kick(_) = control {
+                        let _ = handle.eof(channel).await;
+                        let _ = handle.close(channel).await;
+                        break;
+                    }
+                }
+            };
+            tokio::spawn(relay.instrument(self.log.span()));
+        }
+        Ok((self, session))
+    }
+
+    async fn exec_request(self, channel: ChannelId, _data: &[u8], mut session: Session) -> std::result::Result<(Self, Session), Self::Error> {
+        // There is no shell behind the zine, refuse `ssh host command`
+        session.channel_failure(channel);
+        Ok((self, session))
+    }
+
+    async fn data(mut self, channel: ChannelId, data: &[u8], mut session: Session) -> std::result::Result<(Self, Session), Self::Error> {
+        if self.channel == Some(channel) && !self.handle_keys(data, channel, &mut session).await {
+            self.channel = None;
+        }
+        Ok((self, session))
+    }
+}
+
+fn clamp_size(value: u32) -> u16 {
+    value.min(u16::MAX as u32) as u16
+}
+
@@ -1,84 +1,420 @@
-use std::io::Result;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufReader, Error, ErrorKind, Result};
 use std::io::ErrorKind::WouldBlock;
 use std::net::SocketAddr;
-use chrono::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tracing::{info, warn, Instrument};
 
-// Define the TelnetServer struct that wraps a TcpListener and a TelnetHandler
+use crate::chat;
+use crate::listener::{Connection, Listener, PeerAddr};
+use crate::logging::SessionLog;
+use crate::metrics;
+use crate::sessions::{self, Control};
+use crate::proxy_protocol::{self, ProxySettings};
+
+// Define the TelnetServer struct that wraps any number of listeners (each optionally TLS) and a TelnetHandler
 pub(crate) struct TelnetServer<H: TelnetHandler> {
-    listener: TcpListener,
+    listeners: Vec<(Listener, Option<TlsAcceptor>)>,
+    proxy_protocol: ProxySettings,
+    limits: SessionLimits,
     handler: H,
 }
 
+// Connection cap and idle timeout, shared by every frontend so the cap covers all readers
+#[derive(Clone, Debug)]
+pub(crate) struct SessionLimits {
+    permits: Arc<Semaphore>,
+    pub idle: Option<Duration>,
+}
+
+impl SessionLimits {
+    pub fn new(max_connections: Option<usize>, idle: Option<Duration>) -> Self {
+        let permits = max_connections.unwrap_or(Semaphore::MAX_PERMITS);
+        SessionLimits { permits: Arc::new(Semaphore::new(permits)), idle }
+    }
+
+    // Reserve a reader slot for the lifetime of the returned permit, or None when the server is full
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        SessionLimits::new(None, None)
+    }
+}
+
+pub(crate) const SERVER_FULL: &str = "Too many readers right now, please try again later.\r\n";
+// Sent after chat::UNSPLIT, as readers are idle in the chat more than anywhere
+pub(crate) const IDLE_GOODBYE: &str = "\r\nIdle for too long, see you next time!\r\n";
+// How long a TLS client gets to finish the handshake while holding a connection slot
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 // Implement the TelnetServer struct with generic parameters and methods
 impl<H: TelnetHandler + Send + Sync + 'static + Clone> TelnetServer<H> {
-    // Define the constructor that binds the address to the listener and returns a new TelnetServer instance
-    pub async fn new(addr: &str, handler: H) -> Result<Self> {
+    // Serve plain Telnet on listeners that were already bound (TCP, Unix sockets, inherited file descriptors)
+    pub fn from_listeners(listeners: Vec<Listener>, handler: H) -> Self {
+        TelnetServer {
+            listeners: listeners.into_iter().map(|listener| (listener, None)).collect(),
+            proxy_protocol: ProxySettings::default(),
+            limits: SessionLimits::default(),
+            handler,
+        }
+    }
+
+    // Bind an additional TLS (telnets) listener that serves the same handler with the given certificate and key
+    pub async fn with_tls(mut self, addr: &str, cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let acceptor = tls_acceptor(cert_path, key_path)?;
         let listener = TcpListener::bind(addr).await?;
-        Ok(TelnetServer { listener, handler })
+        self.listeners.push((Listener::Tcp(listener), Some(acceptor)));
+        Ok(self)
+    }
+
+    // Expect a PROXY protocol header on every TCP connection, for running behind a load balancer
+    pub fn with_proxy_protocol(mut self, proxy_protocol: ProxySettings) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    // Share the connection cap and idle timeout with the other frontends
+    pub fn with_limits(mut self, limits: SessionLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
     // Define the async method that runs the server and spawns a new task for each incoming connection
     pub async fn run(self) -> Result<()> {
-        println!("[{}] Listening on: {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), self.listener.local_addr()?);
+        if self.listeners.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "No listeners configured"));
+        }
 
-        loop {
-            let (stream, _addr) = self.listener.accept().await?;
-            let handler = self.handler.clone();
+        let mut accept_loops = JoinSet::new();
+        for (listener, tls) in self.listeners {
+            let kind = if tls.is_some() { "TLS" } else { "Telnet" };
+            info!("Listening for {} on: {}", kind, listener.local_addr()?);
+            accept_loops.spawn(accept_loop(listener, tls, self.proxy_protocol.clone(), self.limits.clone(), self.handler.clone()));
+        }
 
-            tokio::spawn(async move {
-                if let Err(e) = TelnetSession::new(stream, handler).await.unwrap().run().await {
-                    eprintln!("[{}] Error handling connection: {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), e);
-                }
-            });
+        // Accept loops only end on a listener error, which takes the whole server down
+        while let Some(result) = accept_loops.join_next().await {
+            result.map_err(Error::other)??;
         }
+        Ok(())
     }
 }
 
-// Define the TelnetSession struct that wraps a TcpStream and a TelnetHandler
-struct TelnetSession<H: TelnetHandler> {
-    stream: TcpStream,
+// Accept connections on one listener forever, doing any TLS handshake inside the spawned task so a slow client can't stall the listener
+async fn accept_loop<H: TelnetHandler + Clone>(
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    proxy_protocol: ProxySettings,
+    limits: SessionLimits,
     handler: H,
-    addr: SocketAddr,
+) -> Result<()> {
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let handler = handler.clone();
+        let tls = tls.clone();
+        let limits = limits.clone();
+        let frontend = if tls.is_some() { "tls" } else { "telnet" };
+        let proxy_protocol = proxy_protocol.clone();
+
+        tokio::spawn(async move {
+            // The PROXY header comes before the TLS handshake, and before taking a slot so
+            // connections stuck sending one never count against max_connections
+            let addr = match (&mut stream, peer) {
+                (Connection::Tcp(tcp), PeerAddr::Tcp(peer)) => match client_addr(tcp, &proxy_protocol, peer, frontend).await {
+                    Some(addr) => PeerAddr::Tcp(addr),
+                    None => return,
+                },
+                (_, peer) => peer,
+            };
+            let Some(_permit) = limits.try_acquire() else {
+                warn!(peer = %addr, "Turned away: server is full");
+                metrics::rejected(frontend, "full");
+                let _ = stream.write_all(SERVER_FULL.as_bytes()).await;
+                return;
+            };
+            let result = match tls {
+                Some(acceptor) => match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => TelnetSession::new(stream, addr, handler, limits.idle, "tls").run().await,
+                    Ok(Err(e)) => {
+                        warn!(peer = %addr, "TLS handshake failed: {}", e);
+                        metrics::rejected("tls", "tls");
+                        return;
+                    }
+                    Err(_) => {
+                        warn!(peer = %addr, "TLS handshake timed out");
+                        metrics::rejected("tls", "timeout");
+                        return;
+                    }
+                },
+                None => TelnetSession::new(stream, addr, handler, limits.idle, "telnet").run().await,
+            };
+            if let Err(e) = result {
+                warn!("Error handling connection: {}", e);
+            }
+        });
+    }
+}
+
+// Work out the real client address of a TCP connection, logging and returning None for connections that must be dropped
+pub(crate) async fn client_addr(stream: &mut TcpStream, proxy_protocol: &ProxySettings, peer: SocketAddr, frontend: &str) -> Option<SocketAddr> {
+    match proxy_protocol::read_client_addr(stream, proxy_protocol, peer).await {
+        Ok(addr) if sessions::is_banned(addr.ip()) => {
+            info!(peer = %addr, "Refused banned address");
+            metrics::rejected(frontend, "banned");
+            None
+        }
+        Ok(addr) => Some(addr),
+        Err(e) => {
+            warn!(%peer, "Rejected connection: {}", e);
+            metrics::rejected(frontend, "proxy");
+            None
+        }
+    }
+}
+
+// Build a TLS acceptor from a PEM certificate chain and a PEM private key (PKCS#8, RSA or SEC1)
+pub(crate) fn tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor> {
+    let mut cert_reader = BufReader::new(File::open(cert_path)?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut cert_reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("No certificates found in {:?}", cert_path)));
+    }
+
+    let mut key_reader = BufReader::new(File::open(key_path)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(Error::new(ErrorKind::InvalidInput, format!("No private key found in {:?}", key_path))),
+        }
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// Define the TelnetSession struct that wraps a byte stream (plain TCP or TLS) and a TelnetHandler
+struct TelnetSession<S, H: TelnetHandler> {
+    stream: S,
+    handler: H,
+    idle: Option<Duration>,
+    log: SessionLog,
+    // Whether we told the client we'd do the echoing, which hides what it types
+    hidden: bool,
 }
 
 // Implement the TelnetSession struct with generic parameters and methods
-impl<H: TelnetHandler + Send + Sync> TelnetSession<H> {
-    // Define the constructor that creates a new TelnetSession instance from a TcpStream and a TelnetHandler
-    pub async fn new(stream: TcpStream, handler: H) -> Result<Self> {
-        let addr = stream.peer_addr()?;
-        Ok(TelnetSession { stream, handler, addr })
+impl<S: AsyncRead + AsyncWrite + Unpin + Send, H: TelnetHandler + Send + Sync> TelnetSession<S, H> {
+    // Define the constructor that creates a new TelnetSession instance from a stream, its peer address and a TelnetHandler
+    pub fn new(stream: S, addr: PeerAddr, mut handler: H, idle: Option<Duration>, frontend: &'static str) -> Self {
+        let log = SessionLog::start(frontend, addr);
+        handler.on_session(log.id());
+        TelnetSession { stream, handler, idle, log, hidden: false }
     }
 
-    // Define the async method that runs the TelnetSession and handles incoming messages
+    // Define the async method that runs the TelnetSession inside its log span
     pub async fn run(mut self) -> Result<()> {
-        println!("[{}] Got connection from {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), self.addr);
+        let span = self.log.span();
+        self.serve().instrument(span).await
+    }
+
+    // Define the async method that handles incoming messages until the reader leaves
+    async fn serve(&mut self) -> Result<()> {
 
-        self.stream.write_all(self.handler.on_connect().as_bytes()).await?;
+        // Ask the client to report its window size (NAWS) and terminal type, then show the cover
+        self.negotiate(&[IAC, DO, NAWS, IAC, DO, TTYPE]).await?;
+        let cover = self.handler.on_connect();
+        self.send(&cover).await?;
+        self.update_echo().await?;
         loop {
             let mut buffer = [0u8; 1024];
-            match self.stream.read(&mut buffer).await {
+            let read = tokio::select! {
+                read = read_until_idle(&mut self.stream, self.idle, &mut buffer) => match read {
+                    Some(read) => read,
+                    None => {
+                        self.send(&format!("{}{}", chat::UNSPLIT, IDLE_GOODBYE)).await?;
+                        self.stream.shutdown().await?;
+                        self.log.set_reason("idle");
+                        return Ok(());
+                    }
+                },
+                control = self.log.control() => {
+                    self.send(&control.text()).await?;
+                    if let Control::Kick(_) = control {
+                        self.stream.shutdown().await?;
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+            match read {
                 Ok(0) => {
                     return Ok(()) // Connection closed by client
                 },
                 Ok(n) => {
-                    let input = String::from_utf8_lossy(&buffer[..n]).trim().to_string();
-                    let output = self.handler.handle(&input);
+                    let (data, negotiation) = split_telnet_commands(&buffer[..n]);
+                    if let Some((width, height)) = negotiation.size {
+                        self.handler.on_resize(width, height);
+                        self.log.resized(width, height);
+                    }
+                    if negotiation.will_ttype {
+                        self.negotiate(&[IAC, SB, TTYPE, TTYPE_SEND, IAC, SE]).await?;
+                    }
+                    if let Some(terminal) = negotiation.terminal {
+                        self.log.set_terminal(&terminal);
+                    }
+                    // Pure negotiation packets carry no reader input
+                    if data.is_empty() {
+                        continue;
+                    }
+                    let input = String::from_utf8_lossy(&data);
+                    self.log.received(&input);
+                    let input = input.trim().to_string();
+                    let mut output = self.handler.handle(&input);
+                    if let Some(settled) = self.handler.settle().await {
+                        output = settled;
+                    }
+                    self.log.observe(&self.handler);
                     if output == self.handler.quit() {
-                        self.stream.write_all(self.handler.on_quit().as_bytes());
-                        self.stream.shutdown();
-                        println!("[{}] Disconnection from {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), self.addr);
+                        let goodbye = self.handler.on_quit();
+                        self.send(&goodbye).await?;
+                        self.stream.shutdown().await?;
+                        self.log.set_reason("quit");
                         return Ok(());
                     }
-                    self.stream.write_all(output.as_bytes()).await?;
+                    self.send(&output).await?;
+                    self.update_echo().await?;
                 }
                 Err(ref e) if e.kind() == WouldBlock => continue, // Non-blocking error, continue looping
-                Err(e) => return Err(e.into()), // Propagate any other errors
+                Err(e) => return Err(e), // Propagate any other errors
+            }
+        }
+    }
+
+    // Write to the reader, counting the bytes for the metrics and recording them
+    async fn send(&mut self, output: &str) -> Result<()> {
+        self.stream.write_all(output.as_bytes()).await?;
+        self.log.sent(output);
+        Ok(())
+    }
+
+    // Take over echoing (and then echo nothing) while the handler asks for a password, and hand it back after
+    async fn update_echo(&mut self) -> Result<()> {
+        let hidden = self.handler.hide_input();
+        if hidden != self.hidden {
+            self.hidden = hidden;
+            self.negotiate(&[IAC, if hidden { WILL } else { WONT }, ECHO]).await?;
+        }
+        Ok(())
+    }
+
+    // Write Telnet commands, which are counted but kept out of the recording
+    async fn negotiate(&mut self, command: &[u8]) -> Result<()> {
+        self.stream.write_all(command).await?;
+        self.log.sent_control(command.len());
+        Ok(())
+    }
+}
+
+// Read from the reader, or None once they have been idle too long
+async fn read_until_idle<S: AsyncRead + Unpin>(stream: &mut S, idle: Option<Duration>, buffer: &mut [u8]) -> Option<Result<usize>> {
+    match idle {
+        Some(idle) => timeout(idle, stream.read(buffer)).await.ok(),
+        None => Some(stream.read(buffer).await),
+    }
+}
+
+const IAC: u8 = 255;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+const NAWS: u8 = 31;
+const TTYPE: u8 = 24;
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+
+// What the client told us about its terminal in one packet
+#[derive(Debug, Default)]
+struct Negotiation {
+    // The last NAWS window size reported
+    size: Option<(u16, u16)>,
+    // The client agreed to send its terminal type and is waiting to be asked
+    will_ttype: bool,
+    terminal: Option<String>,
+}
+
+// Strip Telnet commands out of a packet, returning the remaining data and what the commands negotiated
+fn split_telnet_commands(bytes: &[u8]) -> (Vec<u8>, Negotiation) {
+    let mut data = Vec::with_capacity(bytes.len());
+    let mut negotiation = Negotiation::default();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != IAC {
+            data.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            // Escaped 0xff data byte
+            Some(&IAC) => {
+                data.push(IAC);
+                i += 2;
+            }
+            // Sub-negotiation runs until IAC SE
+            Some(&SB) => {
+                let start = i + 2;
+                let end = (start..bytes.len().saturating_sub(1))
+                    .find(|&j| bytes[j] == IAC && bytes[j + 1] == SE)
+                    .unwrap_or(bytes.len());
+                let sub = &bytes[start..end];
+                if sub.len() == 5 && sub[0] == NAWS {
+                    let width = u16::from_be_bytes([sub[1], sub[2]]);
+                    let height = u16::from_be_bytes([sub[3], sub[4]]);
+                    negotiation.size = Some((width, height));
+                }
+                if sub.len() > 1 && sub[0] == TTYPE && sub[1] == TTYPE_IS {
+                    negotiation.terminal = Some(String::from_utf8_lossy(&sub[2..]).to_string());
+                }
+                i = end + 2;
+            }
+            Some(&WILL) if bytes.get(i + 2) == Some(&TTYPE) => {
+                negotiation.will_ttype = true;
+                i += 3;
             }
+            // WILL/WONT/DO/DONT carry an option byte
+            Some(&(251..=254)) => i += 3,
+            _ => i += 2,
         }
     }
+    (data, negotiation)
 }
+
 // Define the TelnetHandler trait that represents the Telnet protocol message handler
 pub trait TelnetHandler: Send + Sync + 'static {
     fn handle(&mut self, input: &str) -> String;
@@ -91,6 +427,33 @@ pub trait TelnetHandler: Send + Sync + 'static {
         "".to_string()
     }
 
+    // Called whenever the client reports its terminal size (Telnet NAWS, SSH pty-req and window-change)
+    fn on_resize(&mut self, _width: u16, _height: u16) {}
+
+    // The (section, page) indexes the reader is looking at, if any, for the access log
+    fn current_page(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    // The account the reader signed in to, if any
+    fn identity(&self) -> Option<String> {
+        None
+    }
+
+    // Called once the session is registered, with the ID the admin console and the chat room know it by
+    fn on_session(&mut self, _id: u64) {}
+
+    // Finish what the last input started that's too slow for `handle`, like checking a password.
+    // Frontends wait on it after every line, and show its output, when there is some, instead
+    fn settle(&mut self) -> impl Future<Output = Option<String>> + Send + '_ {
+        async { None }
+    }
+
+    // Whether the reader's next line is a password, which frontends shouldn't echo or record
+    fn hide_input(&self) -> bool {
+        false
+    }
+
     fn quit(&self) -> String {
         let quit = [255, 253, 18];
         String::from_utf8_lossy(&quit).to_string()
@@ -112,4 +475,4 @@ pub trait TelnetHandler: Send + Sync + 'static {
 //     fn handle(&mut self, input: &str) -> String {
 //         input.to_string() + "\r\n"
 //     }
-// }
\ No newline at end of file
+// }
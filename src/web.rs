@@ -0,0 +1,216 @@
+use std::io::{Error, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use serde::Deserialize;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{info, warn, Instrument};
+
+use crate::chat;
+use crate::line_editor::{KeyEvent, LineEditor};
+use crate::logging::SessionLog;
+use crate::metrics;
+use crate::sessions::{self, Control};
+use crate::telnet::{SessionLimits, TelnetHandler, IDLE_GOODBYE, SERVER_FULL};
+
+const INDEX_HTML: &str = include_str!("web_terminal.html");
+
+// Define the WebServer struct that serves a browser terminal and bridges its WebSocket to a TelnetHandler
+pub(crate) struct WebServer<H: TelnetHandler> {
+    listeners: Vec<std::net::TcpListener>,
+    limits: SessionLimits,
+    handler: H,
+}
+
+impl<H: TelnetHandler + Send + Sync + 'static + Clone> WebServer<H> {
+    // Bind the HTTP listeners up front so address errors surface at startup
+    pub async fn new(addrs: &[String], handler: H) -> Result<Self> {
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            listeners.push(tokio::net::TcpListener::bind(addr).await?.into_std()?);
+        }
+        Ok(WebServer { listeners, limits: SessionLimits::default(), handler })
+    }
+
+    // Share the connection cap and idle timeout with the other frontends
+    pub fn with_limits(mut self, limits: SessionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Serve the terminal page on / and the zine session on /ws
+    pub async fn run(self) -> Result<()> {
+        let app = Router::new()
+            .route("/", get(index))
+            .route("/ws", get(ws_upgrade::<H>))
+            .with_state((self.handler, self.limits));
+
+        let mut servers = JoinSet::new();
+        for listener in self.listeners {
+            info!("Listening for HTTP on: {}", listener.local_addr()?);
+            let server = axum::Server::from_tcp(listener)
+                .map_err(Error::other)?
+                .serve(app.clone().into_make_service_with_connect_info::<SocketAddr>());
+            servers.spawn(server);
+        }
+        while let Some(result) = servers.join_next().await {
+            result.map_err(Error::other)?.map_err(Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+async fn ws_upgrade<H: TelnetHandler + Clone>(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State((handler, limits)): State<(H, SessionLimits)>,
+) -> Response {
+    if sessions::is_banned(addr.ip()) {
+        info!(peer = %addr, "Refused banned address");
+        metrics::rejected("web", "banned");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    ws.on_upgrade(move |mut socket| async move {
+        let Some(_permit) = limits.try_acquire() else {
+            warn!(peer = %addr, "Turned away: server is full");
+            metrics::rejected("web", "full");
+            let _ = socket.send(Message::Text(SERVER_FULL.to_string())).await;
+            return;
+        };
+        let session = WebSession::new(socket, addr, handler, limits.idle);
+        let span = session.log.span();
+        if let Err(e) = session.run().instrument(span.clone()).await {
+            span.in_scope(|| warn!("Error handling WebSocket connection: {}", e));
+        }
+    })
+}
+
+// Receive the next frame, or None once the reader has been idle too long
+async fn recv_until_idle(socket: &mut WebSocket, idle: Option<Duration>) -> Option<Option<std::result::Result<Message, axum::Error>>> {
+    match idle {
+        Some(idle) => timeout(idle, socket.recv()).await.ok(),
+        None => Some(socket.recv().await),
+    }
+}
+
+// Messages the browser terminal sends as JSON text frames
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+}
+
+// Define the WebSession struct that adapts one WebSocket onto a TelnetHandler
+struct WebSession<H: TelnetHandler> {
+    socket: WebSocket,
+    handler: H,
+    idle: Option<Duration>,
+    editor: LineEditor,
+    log: SessionLog,
+}
+
+impl<H: TelnetHandler> WebSession<H> {
+    fn new(socket: WebSocket, addr: SocketAddr, mut handler: H, idle: Option<Duration>) -> Self {
+        let log = SessionLog::start("web", addr);
+        handler.on_session(log.id());
+        WebSession { socket, handler, idle, editor: LineEditor::new(), log }
+    }
+
+    // Wait for the next frame, relaying admin messages meanwhile, or None once the socket closes,
+    // the reader has been idle too long or has been kicked
+    async fn next_message(&mut self) -> Option<std::result::Result<Message, axum::Error>> {
+        loop {
+            let received = tokio::select! {
+                received = recv_until_idle(&mut self.socket, self.idle) => received,
+                control = self.log.control() => {
+                    let _ = self.send(control.text()).await;
+                    if let Control::Kick(_) = control {
+                        return None;
+                    }
+                    continue;
+                }
+            };
+            match received {
+                Some(message) => return message,
+                None => {
+                    let _ = self.send(format!("{}{}", chat::UNSPLIT, IDLE_GOODBYE)).await;
+                    self.log.set_reason("idle");
+                    return None;
+                }
+            }
+        }
+    }
+
+    async fn run(mut self) -> std::result::Result<(), axum::Error> {
+        // The page always runs xterm.js, which reports its size right after connecting
+        self.log.set_terminal("xterm.js");
+        let cover = self.handler.on_connect();
+        self.send(cover).await?;
+        while let Some(message) = self.next_message().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let keys = match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(ClientMessage::Input { data }) => {
+                    self.log.received(&data);
+                    data
+                }
+                Ok(ClientMessage::Resize { cols, rows }) => {
+                    self.handler.on_resize(cols, rows);
+                    self.log.resized(cols, rows);
+                    continue;
+                }
+                Err(_) => continue, // Ignore anything that isn't ours
+            };
+            for event in self.editor.feed(&keys) {
+                let output = match event {
+                    KeyEvent::Echo(echo) => echo,
+                    KeyEvent::Line(line) => {
+                        let mut output = self.handler.handle(line.trim());
+                        if let Some(settled) = self.handler.settle().await {
+                            output = settled;
+                        }
+                        self.log.observe(&self.handler);
+                        self.editor.hidden = self.handler.hide_input();
+                        if output == self.handler.quit() {
+                            return self.close().await;
+                        }
+                        output
+                    }
+                    KeyEvent::Interrupt => return self.close().await,
+                };
+                self.send(output).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write to the reader, counting the bytes for the metrics and recording them
+    async fn send(&mut self, output: String) -> std::result::Result<(), axum::Error> {
+        self.log.sent(&output);
+        self.socket.send(Message::Text(output)).await
+    }
+
+    async fn close(mut self) -> std::result::Result<(), axum::Error> {
+        let goodbye = self.handler.on_quit();
+        if !goodbye.is_empty() {
+            self.send(goodbye).await?;
+        }
+        self.log.set_reason("quit");
+        self.socket.close().await?;
+        Ok(())
+    }
+}
@@ -1,37 +1,55 @@
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::fs::{self};
-use std::path::{Path};
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
 use handlebars::{Handlebars};
 use std::fmt;
-use chrono::prelude::*;
-use crate::helpers::{color_helper, italic_helper, bold_helper, underline_helper, rainbow_helper, add_helper};
+use crate::search::SearchIndex;
+use crate::helpers::register_helpers;
+use crate::keymap::{Keymap, KeymapConfig};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Magazine {
     pub title: String,
     pub cover: String,
     pub front: String,
+    // Optional help screen template, which gets the generated command list as {{commands}}
+    #[serde(default)]
+    pub help: Option<String>,
     pub sections: Vec<Section>,
+    // Optional keymap for this magazine, applied over the server's
+    #[serde(default)]
+    pub keymap: Option<KeymapConfig>,
     #[serde(default)]
     pub vars: HashMap<String, String>,
     #[serde(skip)]
     pub cover_text: String,
     #[serde(skip)]
-    pub front_text: String
+    pub front_text: String,
+    #[serde(skip)]
+    pub help_text: Option<String>,
+    // The cover and front page templates, rendered again for readers who signed in
+    #[serde(skip)]
+    cover_template: String,
+    #[serde(skip)]
+    front_template: String,
+    // Full-text index over the pages, built once they're rendered
+    #[serde(skip)]
+    pub search: SearchIndex,
 }
 
 impl Magazine {
-    /// Creates a new magazine from a directory path
-    pub fn from_directory(directory: &Path) -> Self {
+    /// Creates a new magazine from a directory path, describing what's wrong with a broken one,
+    /// both at startup and for reloading while readers are connected
+    pub fn try_from_directory(directory: &Path) -> Result<Self, String> {
         // Read the index file to get the magazine's metadata
         let index_file = directory.join("index.json");
         let index_str = fs::read_to_string(&index_file)
-            .unwrap_or_else(|_| panic!("Failed to read index file {:?}", index_file));
+            .map_err(|err| format!("Failed to read index file {:?}: {}", index_file, err))?;
 
         let mut magazine: Magazine =
-            serde_json::from_str(&index_str).expect("Failed to parse index file");
+            serde_json::from_str(&index_str).map_err(|err| format!("Failed to parse index file: {}", err))?;
 
         // Read and parse the pages for each section in the magazine
         magazine.sections = magazine
@@ -39,44 +57,55 @@ impl Magazine {
             .into_iter()
             .map(|section| {
                 let page_directory = directory.join(&section.directory);
-                let pages = section.pages_for_directory(&page_directory);
-                Section {
+                let pages = section.pages_for_directory(&page_directory)?;
+                Ok(Section {
                     title: section.title,
                     author: section.author,
                     directory: section.directory,
                     vars: section.vars,
+                    tags: normalize_tags(section.tags),
                     pages,
-                }
+                })
             })
-            .collect();
+            .collect::<Result<_, String>>()?;
 
         // Read and render the cover and front pages of the magazine
-        magazine.cover_text = read_and_render_page(&directory, &magazine.cover);
-        magazine.front_text = read_and_render_page(&directory, &magazine.front);
+        magazine.cover_text = read_and_render_page(directory, &magazine.cover)?;
+        magazine.front_text = read_and_render_page(directory, &magazine.front)?;
+        magazine.cover_template = magazine.cover_text.clone();
+        magazine.front_template = magazine.front_text.clone();
 
         // Render the front page with the magazine metadata and sections
         let mut engine = Handlebars::new();
         engine.register_template_string("front_text", magazine.front_text.as_str())
-                            .unwrap_or_else(|_| panic!("Failed to register front page"));
+                            .map_err(|err| format!("Failed to register front page: {}", err))?;
         engine.register_template_string("cover_text", magazine.cover_text.as_str())
-                            .unwrap_or_else(|_| panic!("Failed to register cover page"));
+                            .map_err(|err| format!("Failed to register cover page: {}", err))?;
         let context = json!({
             "title": magazine.title.as_str(),
             "sections": magazine.sections,
             "vars": magazine.vars
         });
-        engine.register_helper("color", Box::new(color_helper));
-        engine.register_helper("italic", Box::new(italic_helper));
-        engine.register_helper("bold", Box::new(bold_helper));
-        engine.register_helper("underline", Box::new(underline_helper));
-        engine.register_helper("rainbow", Box::new(rainbow_helper));
-        engine.register_helper("add", Box::new(add_helper));
+        register_helpers(&mut engine);
 
         magazine.front_text = engine.render("front_text", &context)
-                            .unwrap_or_else(|_| panic!("Failed to render front page"));
+                            .map_err(|err| format!("Failed to render front page: {}", err))?;
         magazine.cover_text = engine.render("cover_text", &context)
-                            .unwrap_or_else(|_| panic!("Failed to render cover page"));   
-        magazine
+                            .map_err(|err| format!("Failed to render cover page: {}", err))?;
+        magazine.search = SearchIndex::build(&magazine.sections);
+
+        // The help template is rendered when a reader asks for it, with the commands of their keymap,
+        // but a broken one should stop the magazine loading like any other page
+        if let Some(help) = &magazine.help {
+            let help_text = read_and_render_page(directory, help)?;
+            Handlebars::new().register_template_string("help", &help_text)
+                            .map_err(|err| format!("Failed to register help page: {}", err))?;
+            magazine.help_text = Some(help_text);
+        }
+        if let Some(keymap) = &magazine.keymap {
+            keymap.apply(&Keymap::default()).map_err(|err| format!("Invalid keymap in index file: {}", err))?;
+        }
+        Ok(magazine)
     }
 
     /// Returns a vector containing references to all the sections in the magazine
@@ -87,14 +116,95 @@ impl Magazine {
     pub fn get_section(&self, index: usize) -> Option<&Section> {
         self.sections.get(index)
     }
+
+    /// The cover and front page for a signed-in reader, whose name templates get as {{reader}}
+    pub fn render_for_reader(&self, reader: &str) -> Result<(String, String), handlebars::RenderError> {
+        let mut engine = Handlebars::new();
+        register_helpers(&mut engine);
+        let context = json!({
+            "title": self.title.as_str(),
+            "sections": self.sections,
+            "vars": self.vars,
+            "reader": reader,
+        });
+        Ok((engine.render_template(&self.cover_template, &context)?, engine.render_template(&self.front_template, &context)?))
+    }
+
+    /// Renders the author's help template around the generated command list, None when there isn't one
+    pub fn render_help(&self, commands: &str, reader: Option<&str>) -> Option<Result<String, handlebars::RenderError>> {
+        let template = self.help_text.as_ref()?;
+        let mut engine = Handlebars::new();
+        engine.register_escape_fn(handlebars::no_escape);
+        register_helpers(&mut engine);
+        let context = json!({
+            "title": self.title.as_str(),
+            "sections": self.sections,
+            "vars": self.vars,
+            "commands": commands,
+            "reader": reader,
+        });
+        Some(engine.render_template(template, &context))
+    }
+
+    /// Every tag in this issue with what carries it, in reading order: a whole section
+    /// (0-based, page None) or a single page of one
+    pub fn tags(&self) -> BTreeMap<String, Vec<(usize, Option<usize>)>> {
+        let mut tags: BTreeMap<String, Vec<(usize, Option<usize>)>> = BTreeMap::new();
+        for (index, section) in self.sections.iter().enumerate() {
+            for tag in &section.tags {
+                tags.entry(tag.clone()).or_default().push((index, None));
+            }
+            for (page, content) in section.pages.iter().enumerate() {
+                for tag in content.tags.iter().filter(|tag| !section.tags.contains(tag)) {
+                    tags.entry(tag.clone()).or_default().push((index, Some(page)));
+                }
+            }
+        }
+        tags
+    }
 }
 
+// Tags are matched without regard to case or stray spaces
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|tag| tag.trim().to_lowercase()) {
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+
+/// Loads every issue in the library directories, the subdirectories with an index.json, oldest
+/// first by directory name so zero-padded names like issue-09 and issue-10 stay in order.
+/// An issue that fails to load is logged and left out rather than stopping the server
+pub fn load_library(directories: &[PathBuf]) -> Vec<(PathBuf, Magazine)> {
+    let mut issues: Vec<PathBuf> = directories
+        .iter()
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join("index.json").is_file())
+        .collect();
+    issues.sort_by(|a, b| a.file_name().cmp(&b.file_name()).then_with(|| a.cmp(b)));
+    issues
+        .into_iter()
+        .filter_map(|path| match Magazine::try_from_directory(&path) {
+            Ok(magazine) => Some((path, magazine)),
+            Err(e) => {
+                tracing::warn!(issue = ?path, "Left out of the library: {}", e);
+                None
+            }
+        })
+        .collect()
+}
 
 /// Reads and renders a page file located in the specified directory
-fn read_and_render_page(directory: &Path, page_filename: &str) -> String {
+fn read_and_render_page(directory: &Path, page_filename: &str) -> Result<String, String> {
     let page_path = directory.join(page_filename);
     fs::read_to_string(&page_path)
-        .unwrap_or_else(|_| panic!("Failed to read page file {:?}", page_path))
+        .map_err(|err| format!("Failed to read page file {:?}: {}", page_path, err))
 }
 
 
@@ -105,6 +215,9 @@ pub struct Section {
     pub directory: String,
     #[serde(default)]
     pub vars: HashMap<String, String>,
+    // Topics for browsing across sections, like "fiction" or "hardware"
+    #[serde(default)]
+    pub tags: Vec<String>,
     #[serde(skip)]
     pub pages: Vec<Page>,
 }
@@ -120,50 +233,42 @@ impl Section {
     }
 
     // Get all pages for a given directory
-    fn pages_for_directory(&self, directory: &Path) -> Vec<Page> {
-        let mut pages: Vec<Page> = fs::read_dir(directory)
-            .unwrap_or_else(|err| panic!("Failed to read directory {:?}: {}", directory, err))
-            .filter_map(|entry| {
-                let entry = entry.expect("Failed to read directory entry");
-                if entry.path().is_file() {
-                    let page_number = entry
-                        .file_name()
-                        .to_str()
-                        .and_then(|s| s.trim_end_matches(".txt").parse().ok())
-                        .expect("Failed to parse page number");
-                    let page = Some(Page::from_file(page_number, &entry.path()));
-                    if page.is_some() {
-                            let mut page_unwrapped = page.unwrap();
-                            let mut engine = Handlebars::new();
-                            engine.register_template_string("page_content", page_unwrapped.text.as_str())
-                                                .unwrap_or_else(|err| panic!("Failed to register page: {}", err));
-                            engine.register_helper("color", Box::new(color_helper));
-                            engine.register_helper("italic", Box::new(italic_helper));
-                            engine.register_helper("bold", Box::new(bold_helper));
-                            engine.register_helper("underline", Box::new(underline_helper));
-                            engine.register_helper("rainbow", Box::new(rainbow_helper));
-                            engine.register_helper("add", Box::new(add_helper));
-
-                            let context = json!({
-                                "title": self.title.as_str(),
-                                "author": self.author.as_str(),
-                                "vars": self.vars
-                            });
-                            println!("[{}] Rendering page {} with context: {:?}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), 
-                                                                            page_unwrapped.page_number, context);
-                    
-                            page_unwrapped.text = engine.render("page_content", &context)
-                                                .unwrap_or_else(|err| panic!("Failed to render page: {}", err));
-                            return Some(page_unwrapped);
-                        }
-                    return page;
-                } else {
-                    None
-                }
-            })
-            .collect();
+    fn pages_for_directory(&self, directory: &Path) -> Result<Vec<Page>, String> {
+        let entries = fs::read_dir(directory)
+            .map_err(|err| format!("Failed to read directory {:?}: {}", directory, err))?;
+        let mut pages = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| format!("Failed to read directory entry: {}", err))?;
+            if !entry.path().is_file() {
+                continue;
+            }
+            let page_number = entry
+                .file_name()
+                .to_str()
+                .and_then(|s| s.trim_end_matches(".txt").parse().ok())
+                .ok_or_else(|| format!("Failed to parse page number of {:?}", entry.path()))?;
+            let mut page = Page::from_file(page_number, &entry.path())?;
+            let mut engine = Handlebars::new();
+            engine.register_template_string("page_content", page.text.as_str())
+                                .map_err(|err| format!("Failed to register page: {}", err))?;
+            register_helpers(&mut engine);
+
+            let context = json!({
+                "title": self.title.as_str(),
+                "author": self.author.as_str(),
+                "vars": self.vars
+            });
+            tracing::trace!(section = %self.title, page = page.page_number, "Rendering page");
+
+            page.text = engine.render("page_content", &context)
+                                .map_err(|err| format!("Failed to render page: {}", err))?;
+            if page.title.is_none() {
+                page.title = first_heading(&page.text);
+            }
+            pages.push(page);
+        }
         pages.sort_by_key(|page| page.page_number);
-        pages
+        Ok(pages)
     }
 }
 
@@ -171,22 +276,116 @@ impl Section {
 pub struct Page {
     pub page_number: u32,
     pub text: String,
+    // From the front matter, or the page's first heading once it's rendered
+    #[serde(default)]
+    pub title: Option<String>,
+    // From the front matter, for pages not written by the section's author
+    #[serde(default)]
+    pub author: Option<String>,
+    // From the front matter as a comma-separated list, on top of the section's tags
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl Page {
-    // Create a new page from a file
-    fn from_file(page_number: u32, filename: &Path) -> Self {
+    // Create a new page from a file, taking its title and author from front matter if it has any
+    fn from_file(page_number: u32, filename: &Path) -> Result<Self, String> {
         let sections = fs::read_to_string(filename)
-            .unwrap_or_else(|err| panic!("Failed to read Page file {:?}: {}", filename, err));
-        Self {
+            .map_err(|err| format!("Failed to read Page file {:?}: {}", filename, err))?;
+        let (front_matter, text) = split_front_matter(&sections);
+        Ok(Self {
             page_number,
-            text: sections,
+            text: text.to_string(),
+            title: front_matter.get("title").cloned(),
+            author: front_matter.get("author").cloned(),
+            tags: normalize_tags(front_matter.get("tags").map(|tags| tags.split(',').map(String::from).collect()).unwrap_or_default()),
+        })
+    }
+
+    // The title shown in section indexes: the page's own, or its number when it has none
+    pub fn display_title(&self) -> String {
+        self.title.clone().unwrap_or_else(|| format!("Page {}", self.page_number))
+    }
+}
+
+// Split "key: value" front matter between two "---" lines off the top of a page
+fn split_front_matter(text: &str) -> (HashMap<String, String>, &str) {
+    let mut front_matter = HashMap::new();
+    let Some(rest) = text.strip_prefix("---\n").or_else(|| text.strip_prefix("---\r\n")) else {
+        return (front_matter, text);
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim();
+        if line == "---" {
+            return (front_matter, &rest[offset..]);
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            front_matter.insert(key.trim().to_lowercase(), value.trim().to_string());
         }
     }
+    // No closing line, so it wasn't front matter after all
+    (HashMap::new(), text)
+}
+
+// Longest title taken from a page's first line
+const MAX_TITLE: usize = 60;
+
+// The first line of a rendered page with its styling and any Markdown-style "#" removed
+fn first_heading(text: &str) -> Option<String> {
+    let plain = crate::ansi::strip(text);
+    let line = plain.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let heading = line.trim_start_matches('#').trim();
+    if heading.chars().count() > MAX_TITLE {
+        // The page opens with prose rather than a heading, so its first words will do
+        let mut title: String = heading.chars().take(MAX_TITLE - 3).collect();
+        title.push_str("...");
+        return Some(title);
+    }
+    (!heading.is_empty()).then(|| heading.to_string())
 }
 
 impl fmt::Display for Page {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.text)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_magazine_loads() {
+        let magazine = Magazine::try_from_directory(Path::new("examples/example-zine")).unwrap();
+        assert!(!magazine.sections.is_empty());
+        assert!(magazine.sections.iter().all(|section| !section.pages.is_empty()));
+    }
+
+    #[test]
+    fn broken_magazines_are_errors() {
+        let missing = Magazine::try_from_directory(Path::new("examples/no-such-zine")).unwrap_err();
+        assert!(missing.contains("Failed to read index file"), "{}", missing);
+    }
+
+    #[test]
+    fn library_holds_only_issues() {
+        // examples holds the example zine and files that aren't issues
+        let library = load_library(&[PathBuf::from("examples"), PathBuf::from("examples/no-such-library")]);
+        let found: Vec<&Path> = library.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(found, [Path::new("examples/example-zine")]);
+        assert_eq!(library[0].1.title, "Telzine Issue #1");
+    }
+
+    #[test]
+    fn front_matter() {
+        let (front_matter, text) = split_front_matter("---\ntitle: Why it rules\nTags: a, b\n---\nBody\n");
+        assert_eq!(front_matter.get("title").map(String::as_str), Some("Why it rules"));
+        assert_eq!(front_matter.get("tags").map(String::as_str), Some("a, b"));
+        assert_eq!(text, "Body\n");
+        // Without a closing line it's part of the page
+        let (front_matter, text) = split_front_matter("---\ntitle: x\nBody\n");
+        assert!(front_matter.is_empty());
+        assert_eq!(text, "---\ntitle: x\nBody\n");
+    }
+}
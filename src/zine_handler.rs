@@ -1,27 +1,201 @@
-use crate::{telnet::TelnetHandler, zine::Magazine};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use crate::{
+    accounts::{self, LoginError, SignedIn},
+    ansi,
+    bookmarks::{self, Owner, Place, ResumeError, Resumed, MAX_BOOKMARKS},
+    chat,
+    comments::{self, MAX_COMMENT, MAX_LINES},
+    keymap::{Action, Keymap},
+    sessions::{self, Control},
+    telnet::TelnetHandler,
+    zine::Magazine,
+};
 use crossterm::{
     cursor::MoveTo,
-    style::{style, Stylize},
+    style::{style, Color, Stylize},
     terminal::{Clear, ClearType},
 };
 
+// The magazine being served, replaced in place when the admin console reloads it
+pub(crate) type Shelf = Arc<RwLock<Arc<Magazine>>>;
+
+// Something a reader can pick from a list of search or tag hits: the back issue it's in, None for
+// this one, and a 0-based section and page, or a whole section when the page is None
+type Hit = (Option<usize>, usize, Option<usize>);
+
 #[derive(Clone, Debug)]
 enum ZineState {
     Front,
     Reading { section: usize, page: usize },
+    // A page of one of the back issues (0-based in the library), numbered like Reading
+    BackIssue { issue: usize, section: usize, page: usize },
+    // The table of contents of a section (0-based)
+    Index { section: usize },
+    // A list of search or tag hits the reader can pick from by number
+    Results { hits: Vec<Hit> },
+    // The tags in every issue, in the order they were listed
+    Tags { tags: Vec<String> },
+    // The help screen, over whatever the reader was looking at
+    Help { previous: Box<ZineState> },
+    // Signing in or registering before the cover, one prompt at a time
+    Login { step: LoginStep },
+    // Readers' comments on a section (0-based)
+    Comments { section: usize },
+    // A comment being written, a line at a time
+    Compose { section: usize, lines: Vec<String> },
+    // A guest picking the nickname they'll chat under
+    ChatNick,
+    // In the chat room, where everything typed is said to the room or is a /command
+    Chat,
+}
+
+#[derive(Clone, Debug)]
+enum LoginStep {
+    // Sign in, register or read as a guest
+    Choose,
+    Name { register: bool },
+    Password { register: bool, name: String },
+    // A new password typed a second time
+    Confirm { name: String, password: String },
+    // Waiting on the password to be checked, or hashed for a new account, which `settle` does
+    Checking { register: bool, name: String, password: String },
 }
 
+// Wrong passwords allowed before the reader is disconnected
+const MAX_FAILED_LOGINS: u8 = 3;
+// Comments one session can leave, so nobody floods the moderation queue
+const MAX_COMMENTS_PER_SESSION: usize = 3;
+// Most recent comments shown on a section's comment screen
+const COMMENTS_SHOWN: usize = 10;
+// Clear the chat's prompt line and draw the prompt again
+const CHAT_PROMPT: &str = "\r\x1b[2K> ";
+
 #[derive(Clone, Debug)]
 pub struct ZineHandler {
-    magazine: Magazine,
+    shelf: Shelf,
+    // The edition this reader is looking at, taken from the shelf whenever they see the cover
+    magazine: Arc<Magazine>,
+    // The other issues in the library, whose tags are browsed along with this one's
+    back_issues: Arc<Vec<Magazine>>,
     state: ZineState,
+    size: Option<(u16, u16)>,
+    background: Color,
+    // The server's keymap, and what this reader types with once the magazine's own keymap is applied over it
+    server_keymap: Keymap,
+    keymap: Keymap,
+    // Whose bookmarks the reader has: their account's, or a resume code once they ask for
+    // something to be remembered. And whether they came back by handle, which only lets them look
+    reader: Option<Owner>,
+    read_only: bool,
+    // The last page they read, 0-based section and 1-based page like the Reading state
+    place: Option<(usize, usize)>,
+    // The account the reader signed in to, and whether they're past the sign in screen at all
+    account: Option<String>,
+    admitted: bool,
+    failed_logins: u8,
+    comments_posted: usize,
+    // The ID the chat room knows this reader's session by, and the nickname a guest picked there
+    session: Option<u64>,
+    nick: Option<String>,
 }
 
 impl ZineHandler {
     pub fn new(zine: Magazine) -> Self {
+        let magazine = Arc::new(zine);
         ZineHandler {
+            shelf: Arc::new(RwLock::new(magazine.clone())),
             state: ZineState::Front,
-            magazine: zine,
+            magazine,
+            back_issues: Arc::new(Vec::new()),
+            size: None,
+            background: Color::Black,
+            server_keymap: Keymap::default(),
+            keymap: Keymap::default(),
+            reader: None,
+            read_only: false,
+            place: None,
+            account: None,
+            admitted: false,
+            failed_logins: 0,
+            comments_posted: 0,
+            session: None,
+            nick: None,
+        }
+        .with_keymap(Keymap::default())
+    }
+
+    // Use the configured theme background instead of black
+    pub fn with_background(mut self, background: Color) -> Self {
+        self.background = background;
+        self
+    }
+
+    // Use the configured keymap instead of the default one
+    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+        self.server_keymap = keymap;
+        self.take_from_shelf();
+        self
+    }
+
+    // Browse the tags of the other issues in the library too, and read what's tagged there
+    pub fn with_back_issues(mut self, back_issues: Vec<Magazine>) -> Self {
+        self.back_issues = Arc::new(back_issues);
+        self
+    }
+
+    // Where the admin console puts a reloaded magazine
+    pub fn shelf(&self) -> Shelf {
+        self.shelf.clone()
+    }
+
+    // Re-render whatever is on screen from the magazine currently on the shelf
+    pub fn redraw(&mut self) -> String {
+        self.take_from_shelf();
+        self.display_current()
+    }
+
+    // Pick up the magazine currently on the shelf along with its keymap
+    fn take_from_shelf(&mut self) {
+        self.magazine = self.shelf.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        self.keymap = match &self.magazine.keymap {
+            // Checked when the magazine loaded
+            Some(keymap) => keymap.apply(&self.server_keymap).unwrap_or_else(|_| self.server_keymap.clone()),
+            None => self.server_keymap.clone(),
+        };
+    }
+
+    // Show whatever the state says is on screen, falling back to the cover for lists that can't be rebuilt
+    fn display_current(&mut self) -> String {
+        match self.state {
+            ZineState::Reading { section, page } if self.magazine.get_section(section).and_then(|s| s.get_page(page - 1)).is_some() => {
+                self.display_section_page(section, page - 1)
+            }
+            ZineState::Index { section } if self.magazine.get_section(section).is_some() => self.display_section_index(section),
+            ZineState::BackIssue { issue, section, page } => self.display_back_issue_page(issue, section, page - 1),
+            ZineState::Help { .. } => self.display_help(),
+            // Start signing in over, no half-typed password survives a redraw
+            ZineState::Login { .. } => self.display_login(""),
+            ZineState::Comments { section } if self.magazine.get_section(section).is_some() => self.display_comments(section, ""),
+            ZineState::Compose { section, ref lines } if self.magazine.get_section(section).is_some() => {
+                let lines = lines.clone();
+                self.display_compose(section, &lines)
+            }
+            ZineState::Comments { .. } | ZineState::Compose { .. } => {
+                self.state = ZineState::Front;
+                self.on_connect()
+            }
+            ZineState::ChatNick => self.display_nick_prompt(""),
+            ZineState::Chat => self.display_chat(),
+            // The page is gone from the new edition
+            ZineState::Reading { .. } | ZineState::Index { .. } => {
+                self.state = ZineState::Front;
+                self.on_connect()
+            }
+            ZineState::Front | ZineState::Results { .. } | ZineState::Tags { .. } => {
+                self.state = ZineState::Front;
+                self.on_connect()
+            }
         }
     }
 
@@ -34,9 +208,635 @@ impl ZineHandler {
 
     // Display the front page of the zine
     fn display_front_page(&self) -> String {
-        let front_text = self.magazine.front_text.clone();
-        let styled_output = style(front_text).on_black();
-        format!("{}{}\r\n", self.clear_screen(), styled_output)
+        let (_, front_text) = self.cover_and_front();
+        let styled_output = style(front_text).on(self.background);
+        let mut hints = Vec::new();
+        if let Some(key) = self.keymap.key(Action::Help) {
+            hints.push(format!("Type {} for help", key));
+        }
+        if let Some(key) = self.keymap.key(Action::Chat).filter(|_| chat::enabled() && self.session.is_some()) {
+            hints.push(format!("{} to talk with other readers ({} in the chat)", key, chat::who().len()));
+        }
+        let hint = match hints.is_empty() {
+            true => String::new(),
+            false => format!("\r\n{}", hints.join(", ").dark_grey()),
+        };
+        format!("{}{}{}\r\n", self.clear_screen(), styled_output, hint)
+    }
+
+    // List a section's pages with their titles and authors, numbered so the reader can pick one
+    fn display_section_index(&self, section: usize) -> String {
+        let Some(current) = self.magazine.get_section(section) else {
+            return format!("Section {} does not exist\r\n", section + 1);
+        };
+        let width = self.size.map(|(width, _)| width as usize).unwrap_or(80);
+        let mut output = format!("{}{}\r\nby {}\r\n\r\n", self.clear_screen(), current.title.as_str().bold(), current.author);
+        for (i, page) in current.all_pages().into_iter().enumerate() {
+            let author = page.author.as_deref().unwrap_or(&current.author);
+            let details = format!("{}, page {}", author, page.page_number);
+            // Leave room for the number and the details, and cut long titles short
+            let room = width.saturating_sub(details.chars().count() + 10).max(10);
+            output.push_str(&format!("{:>3}> {}  {}\r\n", i + 1, truncate(&page.display_title(), room), details.dark_grey()));
+        }
+        output.push_str("\r\nEnter a page number, ENTER to start reading, or 0 for the front page.");
+        if let Some(key) = self.keymap.key(Action::Index) {
+            output.push_str(&format!("\r\nType {} while reading to come back here.", key));
+        }
+        if let Some(key) = self.keymap.key(Action::Comments) {
+            let count = comments::approved(&current.directory).len();
+            output.push_str(&format!("\r\nType {} for what readers said about it ({}).", key, count));
+        }
+        output
+    }
+
+    // List the pages matching a search, numbered so the reader can jump straight to one
+    fn search(&mut self, query: &str) -> String {
+        if query.is_empty() {
+            let key = self.keymap.key(Action::Search).unwrap_or_default();
+            return format!("{}Type {} followed by the words to look for\r\n", self.clear_screen(), key);
+        }
+        let hits = self.magazine.search.search(query);
+        if hits.is_empty() {
+            self.state = ZineState::Front;
+            return format!("{}No pages match \"{}\".\r\n\r\nPress ENTER to go back to front page.", self.clear_screen(), query);
+        }
+        let mut output = format!("{}Pages matching \"{}\":\r\n\r\n", self.clear_screen(), query);
+        for (i, hit) in hits.iter().enumerate() {
+            let section = self.magazine.get_section(hit.section);
+            let title = section.map(|s| s.title.as_str()).unwrap_or_default();
+            let page = section.and_then(|s| s.get_page(hit.page)).map(|p| p.page_number).unwrap_or_default();
+            output.push_str(&format!("{:>3}> {}, page {}\r\n     {}\r\n\r\n", i + 1, title, page, hit.snippet));
+        }
+        output.push_str("Enter a number to read that page, or ENTER for the front page.");
+        self.state = ZineState::Results { hits: hits.iter().map(|hit| (None, hit.section, Some(hit.page))).collect() };
+        output
+    }
+
+    // The commands readers can type, in the magazine's own help template if it has one
+    fn display_help(&self) -> String {
+        let commands = self.keymap.commands(self.magazine.sections.len());
+        let help = match self.magazine.render_help(&commands, self.account.as_deref()) {
+            Some(Ok(help)) => help,
+            Some(Err(e)) => {
+                tracing::warn!("Failed to render help template: {}", e);
+                format!("{}\r\n\r\n{}", "Commands".bold(), commands)
+            }
+            None => format!("{}\r\n\r\n{}", "Commands".bold(), commands),
+        };
+        format!("{}{}\r\nPress ENTER to go back.", self.clear_screen(), help.replace("\r\n", "\n").replace('\n', "\r\n"))
+    }
+
+    // Every tag in this issue and the back issues with what carries it, this issue's first
+    fn all_tags(&self) -> BTreeMap<String, Vec<Hit>> {
+        let mut tags: BTreeMap<String, Vec<Hit>> = BTreeMap::new();
+        let back_issues = self.back_issues.iter().enumerate().map(|(issue, magazine)| (Some(issue), magazine));
+        for (issue, magazine) in std::iter::once((None, &*self.magazine)).chain(back_issues) {
+            for (tag, tagged) in magazine.tags() {
+                tags.entry(tag).or_default().extend(tagged.into_iter().map(|(section, page)| (issue, section, page)));
+            }
+        }
+        tags
+    }
+
+    // List every tag with how many sections and pages carry it, in every issue
+    fn display_tags(&mut self) -> String {
+        let tags = self.all_tags();
+        if tags.is_empty() {
+            self.state = ZineState::Front;
+            return format!("{}Nothing is tagged yet.\r\n\r\nPress ENTER to go back to front page.", self.clear_screen());
+        }
+        let mut output = format!("{}{}\r\n\r\n", self.clear_screen(), "Topics".bold());
+        for (i, (tag, tagged)) in tags.iter().enumerate() {
+            output.push_str(&format!("{:>3}> {} ({})\r\n", i + 1, tag, tagged.len()));
+        }
+        output.push_str("\r\nEnter a number to see everything tagged with it, or ENTER for the front page.");
+        self.state = ZineState::Tags { tags: tags.into_keys().collect() };
+        output
+    }
+
+    // List the sections and pages tagged with a tag, this issue's and then each back issue's
+    // under its title, numbered so the reader can jump to one
+    fn tagged(&mut self, tag: &str) -> String {
+        let tag = tag.to_lowercase();
+        let Some(tagged) = self.all_tags().remove(&tag) else {
+            self.state = ZineState::Front;
+            let all = self.keymap.key(Action::Tags).map(|key| format!(" Type {} to see them all.", key)).unwrap_or_default();
+            return format!("{}Nothing is tagged \"{}\".{}\r\n\r\nPress ENTER to go back to front page.", self.clear_screen(), tag, all);
+        };
+        let mut output = format!("{}Tagged {}:\r\n\r\n", self.clear_screen(), format!("#{}", tag).bold());
+        let mut heading = None;
+        for (i, &(issue, section, page)) in tagged.iter().enumerate() {
+            let magazine = match issue {
+                Some(issue) => &self.back_issues[issue],
+                None => &*self.magazine,
+            };
+            if issue.is_some() && issue != heading {
+                let gap = if i > 0 { "\r\n" } else { "" };
+                output.push_str(&format!("{}{}\r\n", gap, format!("From {}", magazine.title).bold()));
+                heading = issue;
+            }
+            let Some(current) = magazine.get_section(section) else {
+                continue;
+            };
+            let line = match page.and_then(|page| current.get_page(page)) {
+                Some(content) => format!(
+                    "{}: {} {}",
+                    current.title,
+                    content.display_title(),
+                    format!("(page {})", content.page_number).dark_grey()
+                ),
+                None => format!("{} by {} {}", current.title, current.author, "(section)".dark_grey()),
+            };
+            output.push_str(&format!("{:>3}> {}\r\n", i + 1, line));
+        }
+        output.push_str("\r\nEnter a number to read it, or ENTER for the front page.");
+        self.state = ZineState::Results { hits: tagged };
+        output
+    }
+
+    // This reader's resume code, handing out a new one, starting from the page they're on, the
+    // first time they want something remembered. None when they came back by handle
+    fn reader(&mut self) -> Option<Owner> {
+        if self.read_only {
+            return None;
+        }
+        if self.reader.is_none() {
+            let code = Owner::Code(bookmarks::new_reader());
+            if let Some(place) = self.place.and_then(|(section, page)| Place::of(&self.magazine, section, page - 1)) {
+                bookmarks::update(&code, |reader| reader.last = Some(place));
+            }
+            self.reader = Some(code);
+        }
+        self.reader.clone()
+    }
+
+    // Keep track of the page on screen, and remember it for readers with a code so they can pick up there next time
+    fn remember_place(&mut self) {
+        let ZineState::Reading { section, page } = self.state else {
+            return;
+        };
+        if self.place == Some((section, page)) {
+            return;
+        }
+        self.place = Some((section, page));
+        let (Some(owner), false) = (&self.reader, self.read_only) else {
+            return;
+        };
+        if let Some(place) = Place::of(&self.magazine, section, page - 1) {
+            bookmarks::update(owner, |reader| reader.last = Some(place));
+        }
+    }
+
+    // How to get back to this reader's bookmarks next time: by code, or by handle to look only
+    fn resume_hint(&self) -> String {
+        let code = match (&self.reader, &self.account) {
+            (Some(Owner::Account(_)), Some(account)) => return format!("Sign in as {} next time to pick up where you left off.", account),
+            (Some(Owner::Code(code)), _) => code,
+            _ => return String::new(),
+        };
+        let Some(key) = self.keymap.key(Action::Resume) else {
+            return String::new();
+        };
+        if self.read_only {
+            return format!("Type {} followed by your resume code next time to change your bookmarks.", key);
+        }
+        match bookmarks::handle(code) {
+            Some(handle) => format!("Type {} {} next time to pick up where you left off, or {} {} to look at your bookmarks.", key, code, key, handle),
+            None => format!("Type {} {} next time to pick up where you left off.", key, code),
+        }
+    }
+
+    // What readers who came back by handle are told when they try to change something
+    fn read_only_note(&self) -> String {
+        let key = self.keymap.key(Action::Resume).unwrap_or_default();
+        format!("\r\nYou came back by handle, which only shows your bookmarks. Type {} followed by your resume code to change them.\r\n", key)
+    }
+
+    // Bookmark the page on screen, named after its title unless the reader gave a name
+    fn bookmark(&mut self, name: &str) -> String {
+        let ZineState::Reading { section, page } = self.state else {
+            return "\r\nOpen a page to bookmark it.\r\n".to_string();
+        };
+        let Some(place) = Place::of(&self.magazine, section, page - 1) else {
+            return String::new();
+        };
+        let name = match name {
+            "" => ansi::strip(&self.magazine.sections[section].pages[page - 1].display_title()),
+            name => truncate(name, 40),
+        };
+        let Some(owner) = self.reader() else {
+            return self.read_only_note();
+        };
+        let mut full = false;
+        bookmarks::update(&owner, |reader| {
+            full = reader.bookmarks.len() >= MAX_BOOKMARKS && !reader.bookmarks.contains_key(&name);
+            if !full {
+                reader.bookmarks.insert(name.clone(), place);
+            }
+        });
+        if full {
+            return format!("\r\nYou already have {} bookmarks, the most there can be.\r\n", MAX_BOOKMARKS);
+        }
+        format!("\r\nBookmarked \"{}\". {}\r\n", name, self.resume_hint())
+    }
+
+    // List where the reader left off and their bookmarks, numbered so they can jump to one
+    fn display_bookmarks(&mut self) -> String {
+        let reader = self.reader.as_ref().and_then(bookmarks::get).unwrap_or_default();
+        let mut output = format!("{}{}\r\n\r\n", self.clear_screen(), "Bookmarks".bold());
+        let mut hits = Vec::new();
+        let last = reader.last.and_then(|place| place.find(&self.magazine));
+        let marked = reader.bookmarks.iter().filter_map(|(name, place)| Some((name.as_str(), place.find(&self.magazine)?)));
+        for (name, (section, page)) in last.map(|last| ("Where you left off", last)).into_iter().chain(marked) {
+            let current = &self.magazine.sections[section];
+            let details = format!("({}, page {})", current.title, current.pages[page].page_number);
+            hits.push((None, section, Some(page)));
+            output.push_str(&format!("{:>3}> {} {}\r\n", hits.len(), name, details.dark_grey()));
+        }
+        if hits.is_empty() {
+            self.state = ZineState::Front;
+            let hint = self.keymap.key(Action::Bookmark).map(|key| format!(" Type {} while reading to bookmark a page.", key)).unwrap_or_default();
+            return format!("{}No bookmarks yet.{}\r\n\r\nPress ENTER to go back to front page.", self.clear_screen(), hint);
+        }
+        output.push_str("\r\nEnter a number to go there, or ENTER for the front page.");
+        if self.reader.is_some() {
+            output.push_str(&format!("\r\n{}", self.resume_hint()));
+        }
+        self.state = ZineState::Results { hits };
+        output
+    }
+
+    // Pick up where a returning reader left off, by the code they were given or the handle they picked
+    fn resume(&mut self, code_or_handle: &str) -> String {
+        if code_or_handle.is_empty() {
+            let key = self.keymap.key(Action::Resume).unwrap_or_default();
+            return format!("\r\nType {} followed by your resume code or handle.\r\n", key);
+        }
+        let (code, read_only) = match bookmarks::lookup(code_or_handle, self.session.and_then(sessions::ip)) {
+            Ok(Resumed::Code(code)) => (code, false),
+            Ok(Resumed::Handle(code)) => (code, true),
+            Err(ResumeError::Unknown) => return format!("\r\nNo reader has the code or handle {}.\r\n", code_or_handle),
+            Err(ResumeError::Throttled) => return "\r\nToo many codes that didn't match lately, try again later.\r\n".to_string(),
+        };
+        let owner = Owner::Code(code);
+        self.reader = Some(owner.clone());
+        self.read_only = read_only;
+        match bookmarks::get(&owner).and_then(|reader| reader.last).and_then(|place| place.find(&self.magazine)) {
+            Some((section, page)) => {
+                self.state = ZineState::Reading { section, page: page + 1 };
+                self.place = Some((section, page + 1));
+                self.display_section_page(section, page)
+            }
+            None => self.display_bookmarks(),
+        }
+    }
+
+    // Let the reader resume by a name of their own instead of their code
+    fn claim_handle(&mut self, handle: &str) -> String {
+        if handle.is_empty() {
+            let key = self.keymap.key(Action::Handle).unwrap_or_default();
+            return format!("\r\nType {} followed by the name you'd like.\r\n", key);
+        }
+        if let Some(account) = &self.account {
+            return format!("\r\nYou're signed in as {}, your bookmarks go where your account goes.\r\n", account);
+        }
+        let Some(Owner::Code(code)) = self.reader() else {
+            return self.read_only_note();
+        };
+        match bookmarks::claim(handle, &code) {
+            Ok(handle) => format!("\r\nYou're {} now. {}\r\n", handle, self.resume_hint()),
+            Err(e) => format!("\r\n{}\r\n", e),
+        }
+    }
+
+    // The cover and front page, with the reader's name in them once they've signed in
+    fn cover_and_front(&self) -> (String, String) {
+        let rendered = self.account.as_deref().map(|account| self.magazine.render_for_reader(account));
+        match rendered {
+            Some(Ok(pages)) => pages,
+            Some(Err(e)) => {
+                tracing::warn!("Failed to render the cover for a signed-in reader: {}", e);
+                (self.magazine.cover_text.clone(), self.magazine.front_text.clone())
+            }
+            None => (self.magazine.cover_text.clone(), self.magazine.front_text.clone()),
+        }
+    }
+
+    // The sign in screen, with a message about the last attempt if there is one
+    fn display_login(&mut self, message: &str) -> String {
+        self.state = ZineState::Login { step: LoginStep::Choose };
+        let guests = if accounts::guests_allowed() { ", or press ENTER to read as a guest" } else { "" };
+        format!(
+            "{}{}\r\n\r\n{}Type login to sign in or register to make an account{}.\r\n",
+            self.clear_screen(),
+            self.magazine.title.as_str().bold(),
+            message,
+            guests
+        )
+    }
+
+    // One step of signing in or registering; quit works until a password is being typed
+    fn handle_login(&mut self, step: LoginStep, input: &str) -> String {
+        match step {
+            LoginStep::Choose => match input.trim().to_lowercase().as_str() {
+                "login" => {
+                    self.state = ZineState::Login { step: LoginStep::Name { register: false } };
+                    "Name: ".to_string()
+                }
+                "register" => {
+                    self.state = ZineState::Login { step: LoginStep::Name { register: true } };
+                    "Pick a name: ".to_string()
+                }
+                "" if accounts::guests_allowed() => {
+                    self.admitted = true;
+                    self.state = ZineState::Front;
+                    self.on_connect()
+                }
+                _ => self.display_login(""),
+            },
+            LoginStep::Name { register } => {
+                let name = match accounts::normalize_name(input) {
+                    Ok(name) if register && accounts::exists(&name) => {
+                        return format!("The name {} is taken.\r\nPick a name: ", name);
+                    }
+                    Ok(name) => name,
+                    Err(e) if register => return format!("{}\r\nPick a name: ", e),
+                    // A name that can't exist gets the same answer as a wrong password
+                    Err(_) => input.trim().to_string(),
+                };
+                self.state = ZineState::Login { step: LoginStep::Password { register, name } };
+                "Password: ".to_string()
+            }
+            LoginStep::Password { register: false, name } => {
+                self.state = ZineState::Login { step: LoginStep::Checking { register: false, name, password: input.to_string() } };
+                String::new()
+            }
+            LoginStep::Password { register: true, name } => {
+                if input.chars().count() < accounts::MIN_PASSWORD {
+                    self.state = ZineState::Login { step: LoginStep::Password { register: true, name } };
+                    return format!("\r\nPasswords need at least {} characters.\r\nPassword: ", accounts::MIN_PASSWORD);
+                }
+                self.state = ZineState::Login { step: LoginStep::Confirm { name, password: input.to_string() } };
+                "\r\nOnce more: ".to_string()
+            }
+            LoginStep::Confirm { name, password } => {
+                if input != password {
+                    self.state = ZineState::Login { step: LoginStep::Password { register: true, name } };
+                    return "\r\nThose didn't match.\r\nPassword: ".to_string();
+                }
+                self.state = ZineState::Login { step: LoginStep::Checking { register: true, name, password } };
+                String::new()
+            }
+            // Only `settle` moves on from here
+            LoginStep::Checking { .. } => String::new(),
+        }
+    }
+
+    // Check the password the reader typed, or make their account, off the async workers
+    async fn check_login(&mut self, register: bool, name: String, password: String) -> String {
+        if register {
+            return match accounts::register(&name, &password).await {
+                Ok(account) => self.sign_in(account),
+                Err(e) => self.display_login(&format!("{}\r\n\r\n", e)),
+            };
+        }
+        let ip = self.session.and_then(sessions::ip);
+        let message = match accounts::verify(&name, &password, ip).await {
+            Ok(account) => return self.sign_in(account),
+            Err(LoginError::Wrong) => "Wrong name or password.",
+            Err(LoginError::Throttled) => "Too many wrong passwords lately, try again later.",
+        };
+        self.failed_logins += 1;
+        tracing::info!(account = %name, "Failed login");
+        if self.failed_logins >= MAX_FAILED_LOGINS {
+            return self.quit();
+        }
+        self.display_login(&format!("{}\r\n\r\n", message))
+    }
+
+    // In with an account: bookmarks follow the account from here on
+    fn sign_in(&mut self, account: SignedIn) -> String {
+        self.reader = Some(Owner::Account(account.id));
+        self.read_only = false;
+        self.account = Some(account.name);
+        self.admitted = true;
+        self.failed_logins = 0;
+        self.state = ZineState::Front;
+        self.on_connect()
+    }
+
+    // The most recent approved comments on a section, under a message about the reader's own if there is one
+    fn display_comments(&mut self, section: usize, message: &str) -> String {
+        let Some(current) = self.magazine.get_section(section) else {
+            return format!("Section {} does not exist\r\n", section + 1);
+        };
+        let mut output = format!("{}{}Comments on {}\r\n\r\n", self.clear_screen(), message, current.title.as_str().bold());
+        let all = comments::approved(&current.directory);
+        if all.is_empty() {
+            output.push_str("No comments yet.\r\n\r\n");
+        } else if all.len() > COMMENTS_SHOWN {
+            output.push_str(&format!("{}\r\n\r\n", format!("{} earlier comments not shown", all.len() - COMMENTS_SHOWN).dark_grey()));
+        }
+        for comment in all.iter().skip(all.len().saturating_sub(COMMENTS_SHOWN)) {
+            let date = comment.posted.get(..10).unwrap_or_default();
+            output.push_str(&format!("{} {}\r\n", comment.author.as_str().bold(), date.dark_grey()));
+            for line in comment.text.lines() {
+                output.push_str(&format!("  {}\r\n", line));
+            }
+            output.push_str("\r\n");
+        }
+        match self.keymap.key(Action::Comment) {
+            Some(key) => output.push_str(&format!("Type {} to leave one, or ENTER to go back to the section.", key)),
+            None => output.push_str("Press ENTER to go back to the section."),
+        }
+        self.state = ZineState::Comments { section };
+        output
+    }
+
+    // Start writing a comment on a section, or say why the reader can't
+    fn start_comment(&mut self, section: usize) -> String {
+        if self.comments_posted >= MAX_COMMENTS_PER_SESSION {
+            return format!("\r\nThat's {} comments already, the most for one visit.\r\n", MAX_COMMENTS_PER_SESSION);
+        }
+        self.state = ZineState::Compose { section, lines: Vec::new() };
+        self.display_compose(section, &[])
+    }
+
+    fn display_compose(&self, section: usize, lines: &[String]) -> String {
+        let title = self.magazine.get_section(section).map(|s| s.title.as_str()).unwrap_or_default();
+        let moderation = if comments::moderated() { " A moderator reads it before it shows." } else { "" };
+        let mut output = format!(
+            "{}Leave a comment on {}\r\n\r\nUp to {} lines and {} characters. ENTER on an empty line sends it, a single . drops it.{}\r\n\r\n",
+            self.clear_screen(),
+            title.bold(),
+            MAX_LINES,
+            MAX_COMMENT,
+            moderation
+        );
+        for line in lines {
+            output.push_str(&format!("> {}\r\n", line));
+        }
+        output.push_str("> ");
+        output
+    }
+
+    // One line of a comment being written; every line is text, commands included
+    fn handle_compose(&mut self, section: usize, mut lines: Vec<String>, input: &str) -> String {
+        match input.trim() {
+            "." => self.display_comments(section, "Comment dropped.\r\n\r\n"),
+            "" => {
+                let text = comments::sanitize(&lines.join("\n"));
+                let Some(current) = self.magazine.get_section(section).filter(|_| !text.is_empty()) else {
+                    return self.display_comments(section, "");
+                };
+                let author = self.account.clone().unwrap_or_else(|| "guest".to_string());
+                let directory = current.directory.clone();
+                let shown = comments::post(&directory, &author, &text);
+                self.comments_posted += 1;
+                let message = if shown { "Thanks for your comment!" } else { "Thanks! Your comment shows once a moderator approves it." };
+                self.display_comments(section, &format!("{}\r\n\r\n", message))
+            }
+            line => {
+                let length: usize = lines.iter().map(|line| line.chars().count() + 1).sum();
+                let reply = if lines.len() >= MAX_LINES || length + line.chars().count() > MAX_COMMENT {
+                    "That's as long as comments get, ENTER on an empty line sends it.\r\n> ".to_string()
+                } else {
+                    lines.push(line.to_string());
+                    "> ".to_string()
+                };
+                self.state = ZineState::Compose { section, lines };
+                reply
+            }
+        }
+    }
+
+    // Into the chat room, by way of picking a nickname for guests who haven't yet
+    fn enter_chat(&mut self) -> String {
+        let Some(id) = self.session.filter(|_| chat::enabled()) else {
+            return "\r\nThe chat is closed.\r\n".to_string();
+        };
+        let Some(nick) = self.account.clone().or_else(|| self.nick.clone()) else {
+            return self.display_nick_prompt("");
+        };
+        match chat::join(id, &nick) {
+            Ok(()) => {
+                self.state = ZineState::Chat;
+                self.display_chat()
+            }
+            // Someone took the guest's nickname while they were away
+            Err(e) if self.account.is_none() => self.display_nick_prompt(&format!("{}\r\n\r\n", e)),
+            Err(e) => format!("\r\n{}\r\n", e),
+        }
+    }
+
+    fn display_nick_prompt(&mut self, message: &str) -> String {
+        self.state = ZineState::ChatNick;
+        format!(
+            "{}{}Pick a nickname for the chat, or press ENTER to go back to the front page.\r\n\r\nNickname: ",
+            self.clear_screen(),
+            message
+        )
+    }
+
+    // A guest's nickname follows the rules for account names and can't be one
+    fn check_nick(&self, nick: &str) -> Result<String, String> {
+        let nick = accounts::normalize_name(nick)?;
+        if accounts::exists(&nick) {
+            return Err(format!("{} belongs to an account, sign in to chat under it.", nick));
+        }
+        Ok(nick)
+    }
+
+    fn handle_nick(&mut self, input: &str) -> String {
+        if input.trim().is_empty() {
+            self.state = ZineState::Front;
+            return self.display_front_page();
+        }
+        match self.check_nick(input) {
+            Ok(nick) => {
+                self.nick = Some(nick);
+                self.enter_chat()
+            }
+            Err(e) => self.display_nick_prompt(&format!("{}\r\n\r\n", e)),
+        }
+    }
+
+    // The chat's split screen: who's here on the top line, the room scrolling below it, and the
+    // reader's prompt on the bottom line, which new lines are added above (see Control::Chat)
+    fn display_chat(&self) -> String {
+        let (width, height) = match self.size {
+            Some((width, height)) if height >= 6 => (width as usize, height),
+            _ => (80, 24),
+        };
+        let who = chat::who();
+        let header = truncate(&format!("{} here: {}", who.len(), who.join(", ")), width.saturating_sub(6));
+        let commands = match self.account {
+            Some(_) => "/me does something, /who, /leave",
+            None => "/me does something, /nick name, /who, /leave",
+        };
+        let mut output = format!("{}{}{} {}", chat::UNSPLIT, self.clear_screen(), "Chat".bold(), header.dark_grey());
+        output.push_str(&format!("\x1b[2;{}r{}", height - 2, MoveTo(0, height - 3)));
+        for line in chat::scrollback(height as usize - 3) {
+            output.push_str(&format!("\r\n{}", line));
+        }
+        output.push_str(&format!("{}{}", MoveTo(0, height - 2), truncate(commands, width).dark_grey()));
+        output.push_str(&format!("{}> ", MoveTo(0, height - 1)));
+        output
+    }
+
+    // A line only this reader sees, added to the room's scrolling part like anything said there
+    fn chat_note(&self, note: &str) -> String {
+        format!("{}{}", Control::Chat(note.dark_grey().to_string()).text(), CHAT_PROMPT)
+    }
+
+    // A line typed in the chat: said to the room unless it's a /command. ENTER on its own redraws
+    // the screen, for readers whose window changed size
+    fn handle_chat(&mut self, input: &str) -> String {
+        let Some(id) = self.session else {
+            self.state = ZineState::Front;
+            return self.display_front_page();
+        };
+        let input = input.trim();
+        if input.is_empty() {
+            return self.display_chat();
+        }
+        let (command, argument) = match input.strip_prefix('/') {
+            Some(command) => command.split_once(' ').map(|(c, a)| (c, a.trim())).unwrap_or((command, "")),
+            None => return self.said(chat::say(id, input)),
+        };
+        match command.to_lowercase().as_str() {
+            "leave" | "quit" | "part" => {
+                chat::leave(id);
+                self.state = ZineState::Front;
+                format!("{}{}", chat::UNSPLIT, self.display_front_page())
+            }
+            "me" if !argument.is_empty() => self.said(chat::act(id, argument)),
+            "who" => self.chat_note(&format!("Here: {}", chat::who().join(", "))),
+            "nick" if self.account.is_some() => self.chat_note("Signed-in readers chat under their account name."),
+            "nick" if !argument.is_empty() => {
+                let renamed = self.check_nick(argument).and_then(|nick| chat::rename(id, &nick).map(|_| nick));
+                match renamed {
+                    Ok(nick) => {
+                        self.nick = Some(nick);
+                        CHAT_PROMPT.to_string()
+                    }
+                    Err(e) => self.chat_note(&e),
+                }
+            }
+            "help" => self.chat_note(&format!(
+                "Up to {} characters a message. /me does something, /nick name, /who, /leave, ENTER redraws the screen",
+                chat::MAX_MESSAGE
+            )),
+            _ => self.chat_note(&format!("Unknown command /{}, /help lists them", command)),
+        }
+    }
+
+    // What was said reaches the reader through the room, like everyone else's messages
+    fn said(&self, result: Result<(), String>) -> String {
+        match result {
+            Ok(()) => CHAT_PROMPT.to_string(),
+            Err(e) => self.chat_note(&e),
+        }
     }
 
     // Display a specific page in a section of the zine
@@ -44,7 +844,7 @@ impl ZineHandler {
         match self.magazine.get_section(section) {
             Some(sec) => {
                 if let Some(text) = sec.get_page(page) {
-                    let styled_output = style(text).on_black();
+                    let styled_output = style(text).on(self.background);
                     format!("{}{}\r\n", self.clear_screen(), styled_output)
                 } else {
                     format!(
@@ -57,22 +857,199 @@ impl ZineHandler {
             None => format!("Section {} does not exist\r\n", section + 1),
         }
     }
+
+    // Display a page of a back issue, with the issue it's from under it
+    fn display_back_issue_page(&self, issue: usize, section: usize, page: usize) -> String {
+        let Some(magazine) = self.back_issues.get(issue) else {
+            return format!("Issue {} does not exist\r\n", issue + 1);
+        };
+        match magazine.get_section(section).and_then(|s| s.get_page(page)) {
+            Some(text) => {
+                let styled_output = style(text).on(self.background);
+                let from = format!("From {}", magazine.title).dark_grey();
+                format!("{}{}\r\n{}\r\n", self.clear_screen(), styled_output, from)
+            }
+            None => format!("Section {} of {} does not have a page {}\r\n", section + 1, magazine.title, page + 1),
+        }
+    }
+
+    // Past the last page of a section, back to the front page on ENTER
+    fn display_end_of_section(&mut self) -> String {
+        self.state = ZineState::Front;
+        let prompt = "Press ENTER to go back to front page.";
+        // Center the prompt when the client told us its window size
+        let position = match self.size {
+            Some((width, height)) => format!(
+                "{}",
+                MoveTo(width.saturating_sub(prompt.len() as u16) / 2, height / 2)
+            ),
+            None => String::new(),
+        };
+        format!("{}{}{}", self.clear_screen(), position, prompt)
+    }
 }
 
 impl TelnetHandler for ZineHandler {
     // When a telnet client connects, display the cover page of the zine
     fn on_connect(&mut self) -> String {
-        let cover_text = self.magazine.cover_text.clone();
-        let styled_output = style(cover_text).on_black();
+        self.take_from_shelf();
+        if accounts::enabled() && !self.admitted {
+            return self.display_login("");
+        }
+        let (cover_text, _) = self.cover_and_front();
+        let styled_output = style(cover_text).on(self.background);
         let output = format!("{}\r\n", styled_output);
         format!("{}{}", self.clear_screen(), output)
     }
 
+    // Remember the client's window size
+    fn on_resize(&mut self, width: u16, height: u16) {
+        self.size = Some((width, height));
+    }
+
+    // Report the page on screen while reading a section
+    fn current_page(&self) -> Option<(usize, usize)> {
+        match self.state {
+            ZineState::Reading { section, page } => Some((section, page - 1)),
+            // Page views are counted for the magazine being served
+            ZineState::BackIssue { .. }
+            | ZineState::Front
+            | ZineState::Index { .. }
+            | ZineState::Results { .. }
+            | ZineState::Tags { .. }
+            | ZineState::Help { .. }
+            | ZineState::Login { .. }
+            | ZineState::Comments { .. }
+            | ZineState::Compose { .. }
+            | ZineState::ChatNick
+            | ZineState::Chat => None,
+        }
+    }
+
+    fn identity(&self) -> Option<String> {
+        self.account.clone()
+    }
+
+    fn on_session(&mut self, id: u64) {
+        self.session = Some(id);
+    }
+
+    async fn settle(&mut self) -> Option<String> {
+        let ZineState::Login { step: LoginStep::Checking { register, name, password } } = &self.state else {
+            return None;
+        };
+        let (register, name, password) = (*register, name.clone(), password.clone());
+        Some(self.check_login(register, name, password).await)
+    }
+
+    fn hide_input(&self) -> bool {
+        matches!(self.state, ZineState::Login { step: LoginStep::Password { .. } | LoginStep::Confirm { .. } })
+    }
+
+    // Tell readers who leave how to come back to where they were
+    fn on_quit(&mut self) -> String {
+        let unsplit = match self.state {
+            ZineState::Chat => chat::UNSPLIT,
+            _ => "",
+        };
+        match &self.reader {
+            Some(_) => format!("{}\r\n{}\r\n", unsplit, self.resume_hint()),
+            None => unsplit.to_string(),
+        }
+    }
+
     // Handle input from the telnet client
     fn handle(&mut self, input: &str) -> String {
-        if input == "x" || input == "X" || input == "exit" || input == "quit" {
-            return self.quit();
+        let output = self.run_command(input);
+        self.remember_place();
+        output
+    }
+}
+
+impl ZineHandler {
+    // Commands work from any screen, anything else is up to the screen
+    fn run_command(&mut self, input: &str) -> String {
+        // Nothing but signing in (or leaving) until the reader is past the sign in screen
+        if let ZineState::Login { step } = &self.state {
+            let step = step.clone();
+            if matches!(step, LoginStep::Choose) && matches!(self.keymap.command(input), Some((Action::Quit, _))) {
+                return self.quit();
+            }
+            return self.handle_login(step, input);
+        }
+        if let ZineState::Compose { section, lines } = &self.state {
+            let (section, lines) = (*section, lines.clone());
+            return self.handle_compose(section, lines, input);
+        }
+        // Quit words are fair game in a conversation, /leave gets out of the chat instead
+        match self.state {
+            ZineState::Chat => return self.handle_chat(input),
+            ZineState::ChatNick => return self.handle_nick(input),
+            _ => {}
+        }
+        match self.keymap.command(input) {
+            Some((Action::Quit, _)) => return self.quit(),
+            Some((Action::Help, _)) => {
+                if !matches!(self.state, ZineState::Help { .. }) {
+                    let previous = std::mem::replace(&mut self.state, ZineState::Front);
+                    self.state = ZineState::Help { previous: Box::new(previous) };
+                }
+                return self.display_help();
+            }
+            Some((Action::Search, query)) => return self.search(query),
+            Some((Action::Tags, _)) => return self.display_tags(),
+            Some((Action::Tag, tag)) => return self.tagged(tag),
+            Some((Action::Bookmark, name)) => return self.bookmark(name),
+            Some((Action::Bookmarks, _)) => return self.display_bookmarks(),
+            Some((Action::Resume, code_or_handle)) => return self.resume(code_or_handle),
+            Some((Action::Handle, handle)) => return self.claim_handle(handle),
+            Some((Action::Comments, _)) => {
+                if let ZineState::Reading { section, .. } | ZineState::Index { section } | ZineState::Comments { section } = self.state {
+                    return self.display_comments(section, "");
+                }
+            }
+            Some((Action::Comment, _)) => {
+                if let ZineState::Reading { section, .. } | ZineState::Index { section } | ZineState::Comments { section } = self.state {
+                    return self.start_comment(section);
+                }
+            }
+            Some((Action::Chat, _)) => return self.enter_chat(),
+            Some((Action::Index, _)) => {
+                if let ZineState::Reading { section, .. } | ZineState::Index { section } = self.state {
+                    self.state = ZineState::Index { section };
+                    return self.display_section_index(section);
+                }
+            }
+            Some((Action::Previous, _)) => {
+                if let ZineState::Reading { section, page } = self.state {
+                    // Back past the first page is the section's table of contents
+                    if page > 1 {
+                        self.state = ZineState::Reading { section, page: page - 1 };
+                        return self.display_section_page(section, page - 2);
+                    }
+                    self.state = ZineState::Index { section };
+                    return self.display_section_index(section);
+                }
+                // Back issues are only read from their tags, so there's no table of contents to go back to
+                if let ZineState::BackIssue { issue, section, page } = self.state {
+                    let page = page.saturating_sub(1).max(1);
+                    self.state = ZineState::BackIssue { issue, section, page };
+                    return self.display_back_issue_page(issue, section, page - 1);
+                }
+            }
+            Some((Action::Front, _)) => {
+                self.state = ZineState::Front;
+                return self.display_front_page();
+            }
+            // Next does whatever ENTER does on this screen
+            Some((Action::Next, _)) => return self.handle_input(""),
+            None => {}
         }
+        self.handle_input(input)
+    }
+
+    // Input that isn't a command: numbers and ENTER, which mean different things on each screen
+    fn handle_input(&mut self, input: &str) -> String {
         match self.state {
             ZineState::Front => {
                 // If the zine is on the front page and the input is a valid section index,
@@ -82,11 +1059,8 @@ impl TelnetHandler for ZineHandler {
                         return self.on_connect();
                     }
                     if index > 0 && index <= self.magazine.sections.len() {
-                        self.state = ZineState::Reading {
-                            section: index - 1,
-                            page: 1,
-                        };
-                        self.display_section_page(index - 1, 0)
+                        self.state = ZineState::Index { section: index - 1 };
+                        self.display_section_index(index - 1)
                     } else {
                         "".to_string()
                     }
@@ -95,6 +1069,84 @@ impl TelnetHandler for ZineHandler {
                     self.display_front_page()
                 }
             }
+            ZineState::Index { section } => {
+                let pages = self.magazine.get_section(section).map(|s| s.pages.len()).unwrap_or(0);
+                // ENTER starts from the first page, a number jumps to that page
+                let page = match input.trim() {
+                    "" => Some(1),
+                    number => number.parse::<usize>().ok().filter(|&page| page > 0 && page <= pages),
+                };
+                match page {
+                    Some(page) if pages > 0 => {
+                        self.state = ZineState::Reading { section, page };
+                        self.display_section_page(section, page - 1)
+                    }
+                    _ => {
+                        self.state = ZineState::Front;
+                        self.display_front_page()
+                    }
+                }
+            }
+            ZineState::Results { ref hits } => match input.trim().parse::<usize>().ok().and_then(|n| hits.get(n.wrapping_sub(1))) {
+                Some(&(None, section, Some(page))) => {
+                    self.state = ZineState::Reading { section, page: page + 1 };
+                    self.display_section_page(section, page)
+                }
+                Some(&(None, section, None)) => {
+                    self.state = ZineState::Index { section };
+                    self.display_section_index(section)
+                }
+                // A whole section of a back issue starts at its first page
+                Some(&(Some(issue), section, page)) => {
+                    let page = page.unwrap_or(0);
+                    self.state = ZineState::BackIssue { issue, section, page: page + 1 };
+                    self.display_back_issue_page(issue, section, page)
+                }
+                None => {
+                    self.state = ZineState::Front;
+                    self.display_front_page()
+                }
+            },
+            ZineState::Tags { ref tags } => match input.trim().parse::<usize>().ok().and_then(|n| tags.get(n.wrapping_sub(1))) {
+                Some(tag) => {
+                    let tag = tag.clone();
+                    self.tagged(&tag)
+                }
+                None => {
+                    self.state = ZineState::Front;
+                    self.display_front_page()
+                }
+            },
+            // Any key leaves the help screen for whatever was there before
+            ZineState::Help { ref mut previous } => {
+                self.state = std::mem::replace(previous, ZineState::Front);
+                self.display_current()
+            }
+            // Signing in is handled before any other input
+            ZineState::Login { .. } => self.display_login(""),
+            // Anything but a command goes back to the section's table of contents
+            ZineState::Comments { section } => {
+                self.state = ZineState::Index { section };
+                self.display_section_index(section)
+            }
+            ZineState::Compose { section, .. } => self.display_comments(section, ""),
+            // Both are handled before any other input
+            ZineState::ChatNick => self.display_nick_prompt(""),
+            ZineState::Chat => self.display_chat(),
+            ZineState::BackIssue { issue, section, page } => {
+                let section_len = self
+                    .back_issues
+                    .get(issue)
+                    .and_then(|magazine| magazine.get_section(section))
+                    .map(|s| s.pages.len())
+                    .unwrap_or(0);
+                if page >= section_len {
+                    self.display_end_of_section()
+                } else {
+                    self.state = ZineState::BackIssue { issue, section, page: page + 1 };
+                    self.display_back_issue_page(issue, section, page)
+                }
+            }
             ZineState::Reading { section, page } => {
                 let section_len = self
                     .magazine
@@ -104,19 +1156,26 @@ impl TelnetHandler for ZineHandler {
                 let next_page = page + 1;
 
                 if page == section_len || next_page > section_len {
-                    self.state = ZineState::Front;
-                    return format!(
-                        "{}Press ENTER to go back to front page.",
-                        self.clear_screen()
-                    );
+                    self.display_end_of_section()
                 } else {
                     self.state = ZineState::Reading {
-                        section: section,
+                        section,
                         page: next_page,
                     };
-                    return self.display_section_page(section, page);
+                    self.display_section_page(section, page)
                 }
             }
         }
     }
 }
+
+// Cut text to at most `max` characters, marking where it was cut
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max.saturating_sub(3)).collect();
+    truncated.push_str("...");
+    truncated
+}
+